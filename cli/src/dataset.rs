@@ -1,9 +1,11 @@
-use std::{borrow::Cow, marker::PhantomData, path::Path};
+use std::{borrow::Cow, io, marker::PhantomData, path::Path};
 
 use ndarray::{array, s, Array1, Array2};
 
 use hdf5::{Dataset, Extents, File, H5Type, Result};
 
+use crate::DatasetReader;
+
 #[derive(Clone)]
 pub struct BufferedDataset<'f, T, D> {
     file: Cow<'f, File>,
@@ -29,9 +31,10 @@ impl<'f, T, D> BufferedDataset<'f, T, D> {
     where
         P: AsRef<Path>,
         S: Into<Extents>,
+        D: H5Type,
     {
         let file = File::create(path)?;
-        let dataset = file.new_dataset::<u64>().shape(shape).create(dataset)?;
+        let dataset = file.new_dataset::<D>().shape(shape).create(dataset)?;
         Ok(BufferedDataset {
             file: Cow::Owned(file),
             dataset,
@@ -42,8 +45,9 @@ impl<'f, T, D> BufferedDataset<'f, T, D> {
     pub fn with_file<S>(file: &'f File, shape: S, dataset: &str) -> Result<Self>
     where
         S: Into<Extents>,
+        D: H5Type,
     {
-        let dataset = file.new_dataset::<u64>().shape(shape).create(dataset)?;
+        let dataset = file.new_dataset::<D>().shape(shape).create(dataset)?;
         Ok(BufferedDataset {
             file: Cow::Borrowed(file),
             dataset,
@@ -59,9 +63,21 @@ impl<'f, T, D> BufferedDataset<'f, T, D> {
         self.file.new_attr::<V>().create(name)?.write_scalar(value)
     }
 
+    pub fn attr<V: H5Type>(&self, name: &str) -> Result<V> {
+        self.file.attr(name)?.read_scalar()
+    }
+
     pub fn size(&self) -> usize {
         *self.dataset.shape().first().expect("dataset has no shape")
     }
+
+    pub fn dim(&self) -> usize {
+        *self
+            .dataset
+            .shape()
+            .get(1)
+            .expect("dataset is not 2-dimensional")
+    }
 }
 
 impl<'f, T, D> BufferedDataset<'f, T, D>
@@ -143,6 +159,30 @@ where
     }
 }
 
+impl<T, D> DatasetReader<T> for BufferedDatasetIter<T, D>
+where
+    T: From<Array1<D>>,
+    D: H5Type + Clone,
+{
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn dim(&self) -> usize {
+        *self
+            .dataset
+            .shape()
+            .get(1)
+            .expect("dataset is not 2-dimensional")
+    }
+
+    fn seek(&mut self, row: usize) -> io::Result<()> {
+        self.cur = row.min(self.len);
+        self.buffer = ArrayIter::empty();
+        Ok(())
+    }
+}
+
 struct ArrayIter<D> {
     array: Array2<D>,
     cur: usize,
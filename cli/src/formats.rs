@@ -0,0 +1,492 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    marker::PhantomData,
+    mem::size_of,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use hdf5::H5Type;
+use ndarray::Array1;
+
+use crate::BufferedDataset;
+
+// Rows of a dataset streamed in order, regardless of the file format
+pub trait DatasetReader<T>: Iterator<Item = T> {
+    // Total number of rows in the dataset
+    fn size(&self) -> usize;
+
+    // Number of elements in each row
+    fn dim(&self) -> usize;
+
+    // Continue reading from `row`
+    fn seek(&mut self, row: usize) -> io::Result<()>;
+}
+
+// Element type of a dataset row
+pub trait Element: H5Type + Copy {
+    const NAME: &'static str;
+    // Type descriptor used in .npy headers
+    const DESCR: &'static str;
+    // Extension of the texmex format storing this type, if any
+    const VECS: Option<&'static str>;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! element {
+    ($t:ty, $descr:literal, $vecs:expr) => {
+        impl Element for $t {
+            const NAME: &'static str = stringify!($t);
+            const DESCR: &'static str = $descr;
+            const VECS: Option<&'static str> = $vecs;
+
+            fn from_le_bytes(bytes: &[u8]) -> Self {
+                <$t>::from_le_bytes(bytes.try_into().expect("element has wrong size"))
+            }
+        }
+    };
+}
+
+element!(u8, "|u1", Some("bvecs"));
+element!(i32, "<i4", Some("ivecs"));
+element!(f32, "<f4", Some("fvecs"));
+element!(u64, "<u8", None);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DatasetFormat {
+    Hdf5,
+    Fvecs,
+    Bvecs,
+    Ivecs,
+    Npy,
+    // Rows of little-endian elements without any header
+    Raw,
+}
+
+impl DatasetFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .to_lowercase();
+
+        Ok(match extension.as_str() {
+            "h5" | "hdf5" => Self::Hdf5,
+            "fvecs" => Self::Fvecs,
+            "bvecs" => Self::Bvecs,
+            "ivecs" => Self::Ivecs,
+            "npy" => Self::Npy,
+            "bin" | "raw" => Self::Raw,
+            _ => bail!(
+                "unknown format of dataset {path:?}, expected one of .h5, .hdf5, .fvecs, .bvecs, .ivecs, .npy, .bin or .raw"
+            ),
+        })
+    }
+}
+
+// Open the dataset at `path` in the format given by its extension. `name` is the dataset to read
+// from HDF5 files, and `dim` the number of elements in each row of raw files.
+pub fn open_dataset<T, D>(path: &Path, name: &str, dim: usize) -> Result<Box<dyn DatasetReader<T>>>
+where
+    T: From<Array1<D>> + 'static,
+    D: Element + 'static,
+{
+    let format = DatasetFormat::from_path(path)?;
+
+    Ok(match format {
+        DatasetFormat::Hdf5 => {
+            Box::new(BufferedDataset::<'_, T, D>::open(&path, name)?.into_iter())
+        }
+        DatasetFormat::Fvecs | DatasetFormat::Bvecs | DatasetFormat::Ivecs => {
+            Box::new(BinaryDatasetIter::<T, D>::vecs(path)?)
+        }
+        DatasetFormat::Npy => Box::new(BinaryDatasetIter::<T, D>::npy(path)?),
+        DatasetFormat::Raw => Box::new(BinaryDatasetIter::<T, D>::raw(path, dim)?),
+    })
+}
+
+// Reader of files storing rows of fixed size back to back after a header
+pub struct BinaryDatasetIter<T, D> {
+    reader: BufReader<File>,
+    // Byte offset of the first row
+    offset: u64,
+    // Bytes preceding the elements of every row
+    prefix: usize,
+    dim: usize,
+    size: usize,
+    cur: usize,
+    buffer: Vec<u8>,
+    _phantom: PhantomData<(T, D)>,
+}
+
+impl<T, D: Element> BinaryDatasetIter<T, D> {
+    fn new(file: File, offset: u64, prefix: usize, dim: usize) -> Result<Self> {
+        let row = (prefix + dim * size_of::<D>()) as u64;
+        let len = file.metadata()?.len().saturating_sub(offset);
+        ensure!(
+            row > 0 && len % row == 0,
+            "file does not consist of rows of {row} bytes"
+        );
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        Ok(Self {
+            reader,
+            offset,
+            prefix,
+            dim,
+            size: (len / row) as usize,
+            cur: 0,
+            buffer: vec![0; row as usize],
+            _phantom: PhantomData,
+        })
+    }
+
+    // texmex format: every row is its dimension as an i32 followed by its elements
+    pub fn vecs(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(OsStr::to_str);
+        if D::VECS != extension {
+            bail!("rows of {path:?} can not be read as {}", D::NAME);
+        }
+
+        let mut file = File::open(path).with_context(|| format!("could not open {path:?}"))?;
+        let mut dim = [0; 4];
+        file.read_exact(&mut dim)?;
+        file.rewind()?;
+
+        Self::new(file, 0, dim.len(), i32::from_le_bytes(dim) as usize)
+    }
+
+    // NumPy format: a header describing a 2-dimensional C-ordered array followed by its elements
+    pub fn npy(path: &Path) -> Result<Self> {
+        let mut file = File::open(path).with_context(|| format!("could not open {path:?}"))?;
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        ensure!(&magic[..6] == b"\x93NUMPY", "{path:?} is not a .npy file");
+
+        // Version 1 stores the header length as a u16, later versions as a u32
+        let header_len = if magic[6] == 1 {
+            let mut len = [0; 2];
+            file.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        } else {
+            let mut len = [0; 4];
+            file.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        };
+
+        let mut header = vec![0; header_len];
+        file.read_exact(&mut header)?;
+        let header = String::from_utf8(header)?;
+        let offset = file.stream_position()?;
+
+        let descr = npy_field(&header, "descr")
+            .and_then(|s| s.split(['\'', '"']).nth(1))
+            .context("missing `descr` in .npy header")?;
+        if descr != D::DESCR {
+            bail!(
+                "elements of {path:?} are `{descr}`, expected `{}`",
+                D::DESCR
+            );
+        }
+
+        if npy_field(&header, "fortran_order").is_some_and(|s| s.starts_with("True")) {
+            bail!("{path:?} is in Fortran order, only C order is supported");
+        }
+
+        let shape = npy_field(&header, "shape")
+            .and_then(|s| s.strip_prefix('('))
+            .and_then(|s| s.split(')').next())
+            .context("missing `shape` in .npy header")?
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<usize>, _>>()?;
+        let [_, dim] = shape[..] else {
+            bail!("{path:?} has shape {shape:?}, expected 2 dimensions");
+        };
+
+        Self::new(file, offset, 0, dim)
+    }
+
+    pub fn raw(path: &Path, dim: usize) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("could not open {path:?}"))?;
+        Self::new(file, 0, 0, dim)
+    }
+}
+
+// Value of `key` in the Python dict literal of a .npy header
+fn npy_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let (_, rest) = header.split_once(&format!("'{key}':"))?;
+    Some(rest.trim_start())
+}
+
+impl<T, D> Iterator for BinaryDatasetIter<T, D>
+where
+    T: From<Array1<D>>,
+    D: Element,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cur == self.size {
+            return None;
+        }
+
+        self.reader
+            .read_exact(&mut self.buffer)
+            .expect("could not read expected rows");
+        self.cur += 1;
+
+        let row = self.buffer[self.prefix..]
+            .chunks_exact(size_of::<D>())
+            .map(D::from_le_bytes)
+            .collect::<Array1<_>>();
+
+        Some(row.into())
+    }
+}
+
+impl<T, D> DatasetReader<T> for BinaryDatasetIter<T, D>
+where
+    T: From<Array1<D>>,
+    D: Element,
+{
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn seek(&mut self, row: usize) -> io::Result<()> {
+        self.cur = row.min(self.size);
+        let position = self.offset + (self.cur * self.buffer.len()) as u64;
+        self.reader.seek(SeekFrom::Start(position))?;
+        Ok(())
+    }
+}
+
+// Encoding of rows given as text, see `decode_row`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RowEncoding {
+    Hex,
+    Base64,
+}
+
+impl FromStr for RowEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "hex" => Self::Hex,
+            "b64" | "base64" => Self::Base64,
+            _ => bail!("unknown encoding {s:?}, expected hex or base64"),
+        })
+    }
+}
+
+// Row of little-endian elements, laid out as in raw files, given as hex or base64 text. Text can be
+// valid in both encodings, so the encoding is given by a `hex:` or `b64:` prefix, or else by
+// `encoding`.
+pub fn decode_row<D: Element>(text: &str, encoding: Option<RowEncoding>) -> Result<Vec<D>> {
+    let text = text.trim();
+    let (encoding, text) = if let Some(text) = text.strip_prefix("hex:") {
+        (RowEncoding::Hex, text)
+    } else if let Some(text) = text.strip_prefix("b64:") {
+        (RowEncoding::Base64, text)
+    } else {
+        let encoding = encoding.context("row has no `hex:` or `b64:` prefix")?;
+        (encoding, text)
+    };
+
+    let bytes = match encoding {
+        RowEncoding::Hex => decode_hex(text)?,
+        RowEncoding::Base64 => decode_base64(text)?,
+    };
+
+    let size = size_of::<D>();
+    ensure!(
+        bytes.len().is_multiple_of(size),
+        "{} bytes are not a whole number of {} elements",
+        bytes.len(),
+        D::NAME
+    );
+
+    Ok(bytes.chunks_exact(size).map(D::from_le_bytes).collect())
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    ensure!(
+        text.len().is_multiple_of(2) && text.is_ascii(),
+        "hex has an odd number of digits"
+    );
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).context("invalid hex"))
+        .collect()
+}
+
+// Standard alphabet, with or without padding
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    // A single character of a group holds only 6 bits, less than a byte
+    let text = text.trim_end_matches('=');
+    ensure!(text.len() % 4 != 1, "base64 has a truncated group");
+
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("invalid character {:?} in base64", c as char),
+        };
+
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hnsw-itu-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn rows<D: Element + 'static>(path: &Path, dim: usize) -> Vec<Vec<D>> {
+        let rows = open_dataset::<Array1<D>, D>(path, "", dim)
+            .unwrap()
+            .map(|row| row.to_vec())
+            .collect();
+        fs::remove_file(path).unwrap();
+        rows
+    }
+
+    #[test]
+    fn read_vecs() {
+        let mut contents = vec![];
+        for row in [[1.0f32, 2.0], [3.0, 4.0], [5.0, 6.0]] {
+            contents.extend(2i32.to_le_bytes());
+            contents.extend(row.iter().flat_map(|x| x.to_le_bytes()));
+        }
+
+        let path = temp_file("vecs.fvecs", &contents);
+        let mut reader = BinaryDatasetIter::<Array1<f32>, f32>::vecs(&path).unwrap();
+        assert_eq!((reader.size(), reader.dim()), (3, 2));
+        reader.seek(2).unwrap();
+        assert_eq!(reader.next().unwrap().to_vec(), vec![5.0, 6.0]);
+
+        assert_eq!(
+            rows::<f32>(&path, 0),
+            vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]
+        );
+
+        let path = temp_file("vecs-as-u8.fvecs", &contents);
+        assert!(BinaryDatasetIter::<Array1<u8>, u8>::vecs(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_npy() {
+        let mut header =
+            String::from("{'descr': '<u8', 'fortran_order': False, 'shape': (2, 3), }");
+        // Header is padded such that the data is 64-byte aligned
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+
+        let mut contents = b"\x93NUMPY\x01\x00".to_vec();
+        contents.extend((header.len() as u16).to_le_bytes());
+        contents.extend(header.as_bytes());
+        contents.extend((1u64..=6).flat_map(u64::to_le_bytes));
+
+        let path = temp_file("npy.npy", &contents);
+        assert_eq!(rows::<u64>(&path, 0), vec![vec![1, 2, 3], vec![4, 5, 6]]);
+
+        let path = temp_file("npy-as-f32.npy", &contents);
+        assert!(open_dataset::<Array1<f32>, f32>(&path, "", 0).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_raw() {
+        let path = temp_file("raw.bin", &(0u8..12).collect::<Vec<_>>());
+        assert_eq!(
+            rows::<u8>(&path, 4),
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9, 10, 11]]
+        );
+
+        let path = temp_file("raw-bad-dim.bin", &[0; 10]);
+        assert!(open_dataset::<Array1<u8>, u8>(&path, "", 4).is_err());
+        fs::remove_file(path).unwrap();
+
+        assert!(DatasetFormat::from_path(Path::new("data.csv")).is_err());
+        assert_eq!(
+            DatasetFormat::from_path(Path::new("data.H5")).unwrap(),
+            DatasetFormat::Hdf5
+        );
+    }
+    #[test]
+    fn decode_text_rows() {
+        let words = [1u64, u64::MAX];
+        let hex = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        assert_eq!(
+            decode_row::<u64>(&format!("hex:{hex}"), None).unwrap(),
+            words
+        );
+        assert_eq!(
+            decode_row::<u64>(&hex, Some(RowEncoding::Hex)).unwrap(),
+            words
+        );
+        assert_eq!(
+            decode_row::<u64>("b64:AQAAAAAAAAD//////////w==", None).unwrap(),
+            words
+        );
+        assert_eq!(
+            decode_row::<u64>("AQAAAAAAAAD//////////w", Some(RowEncoding::Base64)).unwrap(),
+            words
+        );
+
+        // The prefix takes precedence over the encoding, which is needed without a prefix
+        assert_eq!(
+            decode_row::<f32>("b64:AACAPwAAAEA=", Some(RowEncoding::Hex)).unwrap(),
+            vec![1.0, 2.0]
+        );
+        assert!(decode_row::<u64>(&hex, None).is_err());
+
+        assert!(decode_row::<u64>("b64:AQAAAA==", None).is_err());
+        assert!(decode_row::<u8>("b64:not base64!", None).is_err());
+        assert!(decode_row::<u8>("b64:AAAAA", None).is_err());
+        assert!(decode_row::<u8>("hex:abc", None).is_err());
+    }
+}
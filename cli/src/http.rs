@@ -0,0 +1,107 @@
+use std::io::{self, BufRead, Write};
+
+// Largest request body that is read, larger requests are rejected
+pub const MAX_BODY: usize = 64 << 20;
+
+// Minimal HTTP/1.1 request, only what is needed to serve JSON endpoints
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    // Path without the query string
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Read a request line, headers and a body of `Content-Length` bytes
+pub fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let method = method.to_owned();
+    let path = target.split('?').next().unwrap_or_default().to_owned();
+
+    let mut len = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of headers"));
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("malformed Content-Length"))?;
+            }
+        }
+    }
+
+    if len > MAX_BODY {
+        return Err(invalid("request body is too large"));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request { method, path, body })
+}
+
+pub fn write_response(writer: &mut impl Write, status: u16, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        writer,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_and_response() {
+        let raw = b"POST /search?debug=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 7\r\n\r\n{\"k\":1}";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: String::from("POST"),
+                path: String::from("/search"),
+                body: b"{\"k\":1}".to_vec(),
+            }
+        );
+
+        assert!(read_request(&mut &b"GET\r\n\r\n"[..]).is_err());
+        assert!(read_request(&mut &b"GET / HTTP/1.1\r\nHost: x\r\n"[..]).is_err());
+
+        let mut response = vec![];
+        write_response(&mut response, 404, "{}").unwrap();
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}"
+        );
+    }
+}
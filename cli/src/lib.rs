@@ -1,5 +1,17 @@
 pub mod dataset;
+pub mod formats;
+pub mod http;
+pub mod lsh;
+pub mod mih;
+pub mod pq;
 pub mod sketch;
+pub mod vector;
 
 pub use crate::dataset::*;
+pub use crate::formats::*;
+pub use crate::http::*;
+pub use crate::lsh::*;
+pub use crate::mih::*;
+pub use crate::pq::*;
 pub use crate::sketch::*;
+pub use crate::vector::*;
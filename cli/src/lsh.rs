@@ -0,0 +1,200 @@
+use std::f32::consts::TAU;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::Sketch;
+
+const BITS: usize = Sketch::WORDS * 64;
+
+// Random hyperplane LSH (Charikar): bit `i` of a sketch is set if the projection of the vector onto
+// the normal of hyperplane `i` is above threshold `i`. The hamming distance between two sketches
+// then approximates the angle between their vectors.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sketcher {
+    dim: usize,
+    // `BITS` normals of `dim` elements each
+    hyperplanes: Vec<f32>,
+    thresholds: Vec<f32>,
+}
+
+impl Sketcher {
+    // Hyperplanes through the origin with normals drawn from a standard normal distribution
+    pub fn random(dim: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        Self {
+            dim,
+            hyperplanes: gaussian(&mut rng, BITS * dim),
+            thresholds: vec![0.0; BITS],
+        }
+    }
+
+    // Select `BITS` of `candidates` random hyperplanes based on a sample of the vectors. Every
+    // hyperplane is moved to split the sample in half, and hyperplanes are picked greedily such that
+    // their bits are as uncorrelated as possible with the bits picked so far.
+    pub fn learned(sample: &[Vec<f32>], candidates: usize, seed: u64) -> Self {
+        assert!(!sample.is_empty(), "sample must not be empty");
+        assert!(
+            candidates >= BITS,
+            "at least {BITS} candidate hyperplanes are needed"
+        );
+
+        let dim = sample[0].len();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let hyperplanes = gaussian(&mut rng, candidates * dim);
+
+        // Median projection and bits of the sample for every candidate
+        let (thresholds, bits): (Vec<_>, Vec<_>) = hyperplanes
+            .par_chunks(dim)
+            .map(|normal| {
+                let projections = sample.iter().map(|v| dot(normal, v)).collect::<Vec<_>>();
+
+                let mut sorted = projections.clone();
+                let (_, &mut median, _) =
+                    sorted.select_nth_unstable_by(sample.len() / 2, f32::total_cmp);
+
+                let mut bits = vec![0u64; sample.len().div_ceil(64)];
+                for (i, p) in projections.into_iter().enumerate() {
+                    if p > median {
+                        bits[i / 64] |= 1 << (i % 64);
+                    }
+                }
+
+                (median, bits)
+            })
+            .unzip();
+
+        // Largest absolute correlation of every candidate with the bits picked so far
+        let mut correlations = vec![0.0f64; candidates];
+        let mut available = vec![true; candidates];
+        let mut selected = Vec::with_capacity(BITS);
+
+        for _ in 0..BITS {
+            let next = (0..candidates)
+                .filter(|&c| available[c])
+                .min_by(|&a, &b| correlations[a].total_cmp(&correlations[b]))
+                .expect("there are at least `BITS` candidates");
+
+            available[next] = false;
+            selected.push(next);
+
+            let picked = &bits[next];
+            correlations
+                .par_iter_mut()
+                .zip(&bits)
+                .zip(&available)
+                .filter(|(_, &available)| available)
+                .for_each(|((correlation, bits), _)| {
+                    let differ = bits
+                        .iter()
+                        .zip(picked)
+                        .fold(0, |acc, (a, b)| acc + (a ^ b).count_ones());
+                    let c = (1.0 - 2.0 * differ as f64 / sample.len() as f64).abs();
+                    *correlation = f64::max(*correlation, c);
+                });
+        }
+
+        Self {
+            dim,
+            hyperplanes: selected
+                .iter()
+                .flat_map(|&c| &hyperplanes[c * dim..(c + 1) * dim])
+                .copied()
+                .collect(),
+            thresholds: selected.iter().map(|&c| thresholds[c]).collect(),
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn sketch(&self, vector: &[f32]) -> Sketch {
+        assert_eq!(vector.len(), self.dim, "vector has the wrong dimension");
+
+        let mut data = [0; Sketch::WORDS];
+        for (i, (normal, &threshold)) in self
+            .hyperplanes
+            .chunks_exact(self.dim.max(1))
+            .zip(&self.thresholds)
+            .enumerate()
+        {
+            if dot(normal, vector) > threshold {
+                data[i / 64] |= 1 << (i % 64);
+            }
+        }
+
+        Sketch::new(data)
+    }
+}
+
+fn dot(lhs: &[f32], rhs: &[f32]) -> f32 {
+    lhs.iter().zip(rhs).map(|(l, r)| l * r).sum()
+}
+
+// Box-Muller transform of uniform samples
+fn gaussian(rng: &mut StdRng, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|_| {
+            let u1 = 1.0 - rng.gen::<f32>();
+            let u2 = rng.gen::<f32>();
+            (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hnsw_itu::Point;
+
+    use super::*;
+
+    fn random_vectors(rng: &mut StdRng, len: usize, dim: usize) -> Vec<Vec<f32>> {
+        (0..len).map(|_| gaussian(rng, dim)).collect()
+    }
+
+    #[test]
+    fn random_hyperplanes() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let v = gaussian(&mut rng, 16);
+        let near = v.iter().map(|x| x + 0.1).collect::<Vec<_>>();
+        let opposite = v.iter().map(|x| -x).collect::<Vec<_>>();
+
+        let sketcher = Sketcher::random(16, 0);
+        let sketch = sketcher.sketch(&v);
+        assert_eq!(sketch.data, Sketcher::random(16, 0).sketch(&v).data);
+        assert_ne!(sketch.data, Sketcher::random(16, 1).sketch(&v).data);
+
+        assert!(sketch.distance(&sketcher.sketch(&near)) < 200);
+        assert_eq!(sketch.distance(&sketcher.sketch(&opposite)), BITS);
+    }
+
+    #[test]
+    fn learned_hyperplanes_are_balanced() {
+        let mut rng = StdRng::seed_from_u64(1);
+        // Shifted away from the origin, so hyperplanes through the origin would be unbalanced
+        let sample = random_vectors(&mut rng, 1000, 8)
+            .into_iter()
+            .map(|v| v.into_iter().map(|x| x + 3.0).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let sketcher = Sketcher::learned(&sample, 2 * BITS, 0);
+        let sketches = sample
+            .iter()
+            .map(|v| sketcher.sketch(v))
+            .collect::<Vec<_>>();
+
+        for bit in 0..BITS {
+            let set = sketches
+                .iter()
+                .filter(|s| s.data[bit / 64] >> (bit % 64) & 1 == 1)
+                .count();
+            assert!(
+                set.abs_diff(sample.len() / 2) <= 1,
+                "bit {bit} is set {set} times"
+            );
+        }
+    }
+}
@@ -1,23 +1,39 @@
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     iter::repeat,
+    net::{TcpListener, TcpStream},
     path::{Path, PathBuf},
     str::FromStr,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{Context, Result};
-use bincode::{deserialize_from, serialize_into};
+use anyhow::{bail, Context, Result};
+use bincode::{deserialize, deserialize_from, serialize, serialize_into};
 use clap::{arg, Args, Parser, Subcommand, ValueEnum};
-use hdf5::{types::VarLenUnicode, File as Hdf5File};
+use hdf5::{types::VarLenUnicode, File as Hdf5File, H5Type};
 use hnsw_itu::{
-    Bruteforce, Distance, Graph, HNSWBuilder, Index, IndexBuilder, NSWBuilder, NSWOptions, Point,
-    SimpleGraph, HNSW, NSW,
+    distance_ratio, exact_neighbors, recall, tune_ef, Bruteforce, Centroid, Distance, DynIndex,
+    Graph, HNSWBuilder, IVFBuilder, IVFOptions, Idx, IdxExt, Index, IndexBuilder, IndexHandle,
+    LiveIndex, NSWBuilder, NSWOptions, Neighbors, Point, Progress, ProgressFn, Rerank,
+    ShardedIndex, SimpleGraph, Violation, HNSW, IVF, NSW,
 };
-use hnsw_itu_cli::{BufferedDataset, Sketch};
-use ndarray::arr1;
-use serde::{Deserialize, Serialize};
+use hnsw_itu_cli::http;
+use hnsw_itu_cli::{
+    decode_row, distance_from_key, distance_key, open_dataset, BufferedDataset, Cosine,
+    DatasetFormat, DatasetReader, Element, Euclidean, InnerProduct, RowEncoding, Sketch, Sketcher,
+    Vector, MIH,
+};
+use ndarray::{arr1, Array1};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
 use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::{filter, layer::SubscriberExt, reload, util::SubscriberInitExt, Layer};
 
@@ -108,21 +124,38 @@ fn instrumentation(storage: SharedStorage) {
     println!("distance called {distance_count} times");
 }
 
-#[instrument(skip_all)]
-fn build_index(
-    path: &PathBuf,
-    algorithm: Algorithm,
-    options: impl Into<AlgorithmOptions>,
+fn open_points<P: DataPoint>(path: &Path, name: &str) -> Result<Box<dyn DatasetReader<P>>> {
+    info!(?path, name, point_type = ?P::TYPE, "Opening");
+
+    let format = DatasetFormat::from_path(path)?;
+    if format == DatasetFormat::Raw && P::RAW_DIM.is_none() {
+        bail!("raw files are only supported for binary sketches, use .fvecs or .npy for float vectors");
+    }
+
+    let dataset = open_dataset::<P, P::Element>(path, name, P::RAW_DIM.unwrap_or_default())?;
+    if let Some(dim) = P::RAW_DIM {
+        if dataset.dim() != dim {
+            bail!(
+                "rows of {path:?} have {} elements, expected {dim}",
+                dataset.dim()
+            );
+        }
+    }
+
+    Ok(dataset)
+}
+
+fn read_dataset<P: DataPoint>(
+    path: &Path,
+    name: &str,
     start: Option<usize>,
     len: Option<usize>,
-) -> Result<IndexFile<Sketch>> {
-    info!(?path, "Opening");
-    let dataset = BufferedDataset::<'_, Sketch, _>::open(path, "hamming")?;
+) -> Result<(impl Iterator<Item = P>, usize)> {
+    let mut dataset = open_points::<P>(path, name)?;
 
-    let format_size = start.is_none() && len.is_none();
     let skip = start.unwrap_or_default();
     let take = len.unwrap_or(dataset.size());
-    let size = take.min(dataset.size() - skip);
+    let size = take.min(dataset.size().saturating_sub(skip));
 
     if take != size {
         warn!(
@@ -132,71 +165,186 @@ fn build_index(
         );
     }
 
-    let mut count = 0;
-    let dataset_iter = dataset
-        .clone()
-        .into_iter()
-        .skip(skip)
-        .take(take)
-        .inspect(|_| {
-            count += 1;
-            if count % 100000 == 0 {
-                debug!(count, "{}%", count * 100 / size);
-            }
-        });
+    dataset.seek(skip)?;
+
+    Ok((dataset.take(take), size))
+}
 
+#[instrument(skip_all)]
+fn build_index<P: DataPoint>(
+    path: &Path,
+    name: &str,
+    algorithm: Algorithm,
+    options: impl Into<AlgorithmOptions>,
+    start: Option<usize>,
+    len: Option<usize>,
+) -> Result<IndexFile<P>> {
     let mut options = options.into();
+    let checkpoint = options
+        .resume
+        .as_deref()
+        .map(read_checkpoint::<P>)
+        .transpose()?;
+
+    // Rows already in the checkpoint are not read again
+    let done = checkpoint.as_ref().map_or(0, |c| c.builder.size());
+    let format_size = start.is_none() && len.is_none();
+    let (dataset_iter, rest) = read_dataset(
+        path,
+        name,
+        Some(start.unwrap_or_default() + done),
+        len.map(|len| len.saturating_sub(done)),
+    )?;
+    let size = done + rest;
+    let mut dataset_iter = dataset_iter.peekable();
+    let dim = dataset_iter.peek().map(DataPoint::dim);
+
     options.size = Some(options.size.unwrap_or(size));
     info!(
         size,
+        done,
         ?algorithm,
         single_threaded = options.single_threaded,
         "Building index"
     );
     let buildtime_start = SystemTime::now();
 
-    let index = algorithm.create(dataset_iter, options.clone());
+    let mut attrs = ResultAttrs {
+        format_size,
+        start: start.unwrap_or_default(),
+        data: name.to_owned(),
+        size,
+        dim,
+        algo: algorithm,
+        params: match algorithm {
+            Algorithm::Ivf => format!("index=(nlist={:?})", options.nlist),
+            _ => format!(
+                "index=(efc={:?},m={:?},M={:?})",
+                options.ef_construction, options.connections, options.max_connections
+            ),
+        },
+        ..Default::default()
+    };
+
+    let builder = match checkpoint {
+        Some(checkpoint) => {
+            let c = &checkpoint.attrs;
+            if (c.start, c.size, &c.data, c.algo) != (attrs.start, size, &attrs.data, algorithm) {
+                bail!(
+                    "checkpoint is of {:?} on rows {}..{} of {:?}, not {algorithm:?} on rows {}..{} of {name:?}",
+                    c.algo,
+                    c.start,
+                    c.start + c.size,
+                    c.data,
+                    attrs.start,
+                    attrs.start + size
+                );
+            }
+
+            // The build continues with the parameters it was started with
+            attrs = checkpoint.attrs;
+            Some(checkpoint.builder)
+        }
+        None => algorithm.builder(&options)?,
+    };
+
+    let index = match builder {
+        Some(mut builder) => {
+            builder.set_progress(Some(log_progress(size)));
+            extend_graph(&mut builder, dataset_iter, &options, |builder| {
+                let buildtime = buildtime_start.elapsed().unwrap_or(Duration::ZERO);
+                let attrs = ResultAttrs {
+                    buildtime: attrs.buildtime + buildtime.as_secs_f64(),
+                    ..attrs.clone()
+                };
+                match &options.checkpoint {
+                    Some(path) => write_checkpoint(path, &Checkpoint { attrs, builder }),
+                    None => Ok(()),
+                }
+            })?;
+            builder.build()
+        }
+        None => algorithm.create(dataset_iter, options.clone())?,
+    };
     let buildtime_total = buildtime_start.elapsed().unwrap_or(Duration::ZERO);
     let buildtime_per_element = buildtime_total / size as u32;
     info!(
         "Total build time: {:?}, per element: {:?}",
         buildtime_total, buildtime_per_element
     );
+    attrs.buildtime += buildtime_total.as_secs_f64();
 
-    let attrs = ResultAttrs {
-        format_size,
-        size,
-        algo: algorithm,
-        buildtime: buildtime_total.as_secs_f64(),
-        params: format!(
-            "index=(efc={:?},m={:?},M={:?})",
-            options.ef_construction, options.connections, options.max_connections
-        ),
-        ..Default::default()
+    Ok(IndexFile { attrs, index })
+}
+
+// Log the progress of a graph build every 100000 inserted points
+fn log_progress(size: usize) -> ProgressFn {
+    let logged = AtomicUsize::new(0);
+    Arc::new(move |progress: Progress| {
+        let step = progress.inserted / 100_000;
+        if logged.fetch_max(step, Ordering::Relaxed) < step {
+            debug!(
+                inserted = progress.inserted,
+                layers = progress.layers,
+                elapsed = ?progress.elapsed,
+                "{}% inserted",
+                progress.inserted * 100 / size.max(1)
+            );
+        }
+    })
+}
+
+// Insert the points into a graph builder, writing a checkpoint of it every `checkpoint_every` points
+// until all points are inserted
+fn extend_graph<P: DataPoint>(
+    builder: &mut Builders<P>,
+    dataset: impl Iterator<Item = P>,
+    options: &AlgorithmOptions,
+    mut checkpoint: impl FnMut(&Builders<P>) -> Result<()>,
+) -> Result<()> {
+    let Some(every) = options.checkpoint_every else {
+        return builder.try_extend(dataset, options.single_threaded);
     };
 
-    Ok(IndexFile { attrs, index })
+    let mut dataset = dataset.peekable();
+    while dataset.peek().is_some() {
+        builder.try_extend(dataset.by_ref().take(every), options.single_threaded)?;
+
+        if dataset.peek().is_some() {
+            checkpoint(builder)?;
+            info!(size = builder.size(), "Wrote checkpoint");
+        }
+    }
+
+    Ok(())
 }
 
 #[instrument(skip_all)]
-fn query_index<'a>(
-    path: &PathBuf,
-    index: &'a Indexes<Sketch>,
+fn query_index<'a, P: DataPoint>(
+    path: &Path,
+    name: &str,
+    index: &'a (impl Index<P> + Sync),
     attrs: &mut ResultAttrs,
     k: usize,
     ef: usize,
     single_threaded: bool,
-) -> Result<Vec<Vec<Distance<'a, Sketch>>>> {
-    if k > ef {
+) -> Result<Vec<Vec<Distance<'a, P>>>> {
+    // For IVF `ef` is the number of lists to probe, which is usually much smaller than `k`
+    if k > ef && attrs.algo != Algorithm::Ivf {
         error!(
             k,
             ef, "`k` is greater than `ef`, this can have adverse effects"
         );
     }
 
-    info!(?path, "Opening");
-    let queries = BufferedDataset::open(path, "hamming")?;
+    let queries = open_points::<P>(path, name)?;
     let queries_size: u32 = queries.size().try_into().unwrap();
+    if let Some(dim) = attrs.dim.filter(|&dim| dim != queries.dim()) {
+        bail!(
+            "{path:?} holds queries of {} elements, but the index holds points of {dim} elements",
+            queries.dim()
+        );
+    }
 
     info!(k, ef, single_threaded, "Start querying");
     let querytime_start = SystemTime::now();
@@ -221,32 +369,421 @@ fn query_index<'a>(
     Ok(results)
 }
 
+// Start of index files and checkpoints, followed by the version of their format
+const MAGIC: [u8; 8] = *b"hnswitu\0";
+// Raised whenever files written by earlier versions can no longer be read
+const FORMAT_VERSION: u32 = 3;
+
+// Check that a file starts with the magic number and is of the format version of this build
+fn read_format(reader: &mut impl Read, path: &Path) -> Result<()> {
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .with_context(|| format!("Could not read the header of {path:?}"))?;
+    if magic != MAGIC {
+        bail!(
+            "{path:?} is not an index file, or was written before index files had a format version"
+        );
+    }
+
+    let version: u32 = deserialize_from(reader).context("Could not read file header")?;
+    if version != FORMAT_VERSION {
+        bail!(
+            "{path:?} has format version {version}, but this build reads version {FORMAT_VERSION}, rebuild the index with this build"
+        );
+    }
+
+    Ok(())
+}
+
+// Check that a file written by `write_header` holds points of type `P` with node indices as in this
+// build
+fn read_header<P: DataPoint>(reader: &mut impl Read, path: &Path) -> Result<()> {
+    read_format(reader, path)?;
+
+    let point_type: PointType =
+        deserialize_from(&mut *reader).context("Could not read file header")?;
+    if point_type != P::TYPE {
+        bail!(
+            "{path:?} holds {point_type:?} points, expected {:?}",
+            P::TYPE
+        );
+    }
+
+    let idx_bits: u32 = deserialize_from(reader).context("Could not read file header")?;
+    if idx_bits != Idx::BITS {
+        bail!(
+            "{path:?} has {idx_bits}-bit node indices, but this build uses {}-bit indices (see the `idx-u32` feature)",
+            Idx::BITS
+        );
+    }
+
+    Ok(())
+}
+
+fn write_header<P: DataPoint>(writer: &mut impl Write) -> Result<()> {
+    writer.write_all(&MAGIC)?;
+    serialize_into(&mut *writer, &FORMAT_VERSION)?;
+    serialize_into(&mut *writer, &P::TYPE)?;
+    serialize_into(writer, &Idx::BITS)?;
+    Ok(())
+}
+
 #[instrument(skip_all)]
-fn read_index(path: &impl AsRef<Path>) -> Result<IndexFile<Sketch>> {
+fn read_index<P: DataPoint>(
+    path: &impl AsRef<Path>,
+    registry: &Registry<P>,
+) -> Result<IndexFile<P>> {
     info!(path = path.as_ref().to_str(), "Reading index");
 
-    let reader = BufReader::new(File::open(path)?);
-    let index_file: IndexFile<Sketch> = deserialize_from(reader).context("Could not read index")?;
+    let mut reader = BufReader::new(File::open(path)?);
+    read_header::<P>(&mut reader, path.as_ref())?;
+
+    let mut index_file =
+        IndexFile::<P>::read(&mut reader, registry).context("Could not read index")?;
+
+    if let Some(log) = read_log(path.as_ref())? {
+        replay_log(&mut index_file, log)?;
+    }
 
     info!(size = index_file.index.size(), "Read index");
 
     Ok(index_file)
 }
 
+// Path of the log of changes to the index file at `path`
+fn log_path(path: &Path) -> PathBuf {
+    let mut log_path = path.as_os_str().to_owned();
+    log_path.push(".log");
+    PathBuf::from(log_path)
+}
+
+// Change to an index since its last snapshot, with keys as in the index
+#[derive(Serialize, Deserialize, Debug)]
+enum LogEntry<P> {
+    Add(P),
+    Remove(usize),
+}
+
+// Append-only log of changes next to an index file. The log starts with the snapshot id and the
+// generation of the index it follows, such that a log of another snapshot is never replayed, e.g.
+// one left by a crash after a new snapshot was written, and changes already in the index are
+// skipped.
+struct Log<P> {
+    snapshot: u64,
+    generation: u64,
+    entries: Vec<LogEntry<P>>,
+    // Length of the file up to the end of the last complete entry
+    len: u64,
+}
+
+// Every entry of a log follows its length as a `u32` and its checksum as a `u64`, such that an entry
+// cut off at the end can be told apart from a corrupt one
+const ENTRY_HEADER: u64 = 12;
+
+// 64-bit FNV-1a hash of an entry of a log
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// Open the log of the index file at `path` and lock it, such that its index is changed by one
+// process at a time. The lock is held until the file is dropped.
+fn lock_log(path: &Path) -> Result<File> {
+    let path = log_path(path);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    file.lock()
+        .with_context(|| format!("Could not lock {path:?}"))?;
+    Ok(file)
+}
+
+#[instrument(skip_all)]
+fn read_log<P: DataPoint>(path: &Path) -> Result<Option<Log<P>>> {
+    let path = log_path(path);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Could not open {path:?}")),
+    };
+
+    let end = file.metadata()?.len();
+    // An empty log is created by `lock_log` before the first changes are appended
+    if end == 0 {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::new(file);
+    // A log is only missing its header if it was never completely written
+    let Ok((snapshot, generation)) = deserialize_from::<_, (u64, u64)>(&mut reader) else {
+        warn!(?path, "Ignoring log without a header");
+        return Ok(None);
+    };
+
+    let mut log = Log {
+        snapshot,
+        generation,
+        entries: vec![],
+        len: reader.stream_position()?,
+    };
+    while log.len < end {
+        // Only the last entry can be shorter than its length, if it was cut off by a crash while
+        // it was appended
+        let torn = |len: u64| end - log.len < ENTRY_HEADER + len;
+        if torn(0) {
+            warn!(?path, "Ignoring incomplete entry at the end of the log");
+            break;
+        }
+        let (len, checksum): (u32, u64) = deserialize_from(&mut reader)?;
+        if torn(len.into()) {
+            warn!(?path, "Ignoring incomplete entry at the end of the log");
+            break;
+        }
+
+        let mut entry = vec![0; len as usize];
+        reader.read_exact(&mut entry)?;
+        if fnv1a(&entry) != checksum {
+            bail!(
+                "{path:?} is corrupt, the entry at byte {} does not match its checksum",
+                log.len
+            );
+        }
+        log.entries.push(deserialize(&entry).with_context(|| {
+            format!(
+                "{path:?} is corrupt, the entry at byte {} can not be read",
+                log.len
+            )
+        })?);
+        log.len += ENTRY_HEADER + u64::from(len);
+    }
+
+    info!(?path, generation, entries = log.entries.len(), "Read log");
+
+    Ok(Some(log))
+}
+
+fn replay_log<P: DataPoint>(index_file: &mut IndexFile<P>, log: Log<P>) -> Result<()> {
+    if log.snapshot != index_file.attrs.snapshot {
+        warn!(
+            snapshot = index_file.attrs.snapshot,
+            log = log.snapshot,
+            "Ignoring log of another snapshot of the index"
+        );
+        return Ok(());
+    }
+
+    let generation = index_file.index.as_live().map_or(0, LiveIndex::generation);
+    if log.generation > generation {
+        bail!(
+            "Log follows generation {} of the index, but the index is at generation {generation}",
+            log.generation
+        );
+    }
+
+    let skip = (generation - log.generation) as usize;
+    if skip >= log.entries.len() {
+        return Ok(());
+    }
+
+    let live = index_file.live();
+    for entry in log.entries.into_iter().skip(skip) {
+        match entry {
+            LogEntry::Add(point) => {
+                live.add(point);
+            }
+            LogEntry::Remove(key) => live.remove(Idx::from_usize(key))?,
+        }
+    }
+
+    info!(generation = live.generation(), "Replayed log");
+
+    Ok(())
+}
+
+// Append changes that have been made to the index of `snapshot`, which was at `generation` before
+// them, and wait for them to reach the disk
+#[instrument(skip_all)]
+fn append_log<P: DataPoint>(
+    path: &Path,
+    snapshot: u64,
+    generation: u64,
+    entries: &[LogEntry<P>],
+) -> Result<()> {
+    let log = read_log::<P>(path)?;
+    let path = log_path(path);
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+
+    match log {
+        // Incomplete entries at the end are overwritten
+        Some(log)
+            if log.snapshot == snapshot
+                && log.generation + log.entries.len() as u64 == generation =>
+        {
+            file.set_len(log.len)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+        // The log does not follow the index, e.g. after a snapshot, so it is started over
+        _ => {
+            file.set_len(0)?;
+            serialize_into(&mut file, &(snapshot, generation))?;
+        }
+    }
+
+    let mut writer = BufWriter::new(&file);
+    for entry in entries {
+        let entry = serialize(entry)?;
+        let len = u32::try_from(entry.len()).context("Log entry is too large")?;
+        serialize_into(&mut writer, &(len, fnv1a(&entry)))?;
+        writer.write_all(&entry)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    file.sync_data()?;
+
+    info!(?path, entries = entries.len(), "Appended to log");
+
+    Ok(())
+}
+
+// Query several index files as one, each file being a shard starting at the dataset row it was
+// built from
+#[instrument(skip_all)]
+fn read_sharded_index<P: DataPoint>(
+    paths: &[PathBuf],
+    registry: &Registry<P>,
+) -> Result<IndexFile<P>> {
+    if let [path] = paths {
+        return read_index(path, registry);
+    }
+
+    let mut attrs: Option<ResultAttrs> = None;
+    let mut sharded = ShardedIndex::new();
+
+    for path in paths {
+        let index_file = read_index(path, registry)?;
+        let size = index_file.index.size();
+
+        // Keys of the sharded index are global rows, so it starts at row 0
+        let attrs = attrs.get_or_insert_with(|| ResultAttrs {
+            format_size: true,
+            start: 0,
+            size: 0,
+            buildtime: 0.0,
+            params: format!("{},shards={}", index_file.attrs.params, paths.len()),
+            ..index_file.attrs.clone()
+        });
+        if let (Some(dim), Some(shard_dim)) = (attrs.dim, index_file.attrs.dim) {
+            if dim != shard_dim {
+                bail!("{path:?} holds points of {shard_dim} elements, but the shards before it hold points of {dim} elements");
+            }
+        }
+        attrs.dim = attrs.dim.or(index_file.attrs.dim);
+        attrs.size += size;
+        attrs.buildtime += index_file.attrs.buildtime;
+        // Shards tuned separately are queried with the largest of their beamwidths
+        attrs.ef = attrs.ef.max(index_file.attrs.ef);
+
+        info!(start = index_file.attrs.start, size, "Adding shard");
+        sharded.add_shard(index_file.attrs.start, index_file.index);
+    }
+
+    info!(shards = paths.len(), "Assembled sharded index");
+
+    Ok(IndexFile {
+        attrs: attrs.context("No index files given")?,
+        index: Box::new(sharded),
+    })
+}
+
+// Type of the points in an index file, which is written after the format version and before the
+// width of node indices and the index
+fn read_point_type(path: &impl AsRef<Path>) -> Result<PointType> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_format(&mut reader, path.as_ref())?;
+    deserialize_from(reader).context("Could not read index")
+}
+
 #[instrument(skip_all)]
-fn write_index<P: Serialize>(path: &impl AsRef<Path>, index_file: &IndexFile<P>) -> Result<()> {
+fn write_index<P: DataPoint>(path: &impl AsRef<Path>, index_file: &mut IndexFile<P>) -> Result<()> {
     info!(
         path = path.as_ref().to_str(),
         size = index_file.index.size(),
+        point_type = ?P::TYPE,
         "Serializing"
     );
 
-    let writer = BufWriter::new(File::create(path)?);
-    serialize_into(writer, index_file)?;
+    // The index holds all changes in the log, which can be removed once it is written. Should that
+    // fail, the log is not replayed onto the new snapshot.
+    index_file.attrs.snapshot = rand::random();
+    let path = path.as_ref();
+    write_atomically(path, |writer| {
+        write_header::<P>(writer)?;
+        index_file.write(writer)
+    })?;
+
+    match fs::remove_file(log_path(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)?,
+        _ => Ok(()),
+    }
+}
+
+// Write a file next to `path` and move it in place, such that readers never see half a file
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    write(&mut writer)?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&tmp_path, path)?;
 
     Ok(())
 }
 
+// Graph index built from the first rows of a dataset range, such that the build can be resumed from
+// the rows after them. The attributes are those of the finished index, with the time spent so far.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<B> {
+    attrs: ResultAttrs,
+    builder: B,
+}
+
+#[instrument(skip_all)]
+fn read_checkpoint<P: DataPoint>(path: &Path) -> Result<Checkpoint<Builders<P>>> {
+    info!(?path, "Reading checkpoint");
+
+    let mut reader = BufReader::new(File::open(path)?);
+    read_header::<P>(&mut reader, path)?;
+    let checkpoint: Checkpoint<Builders<P>> =
+        deserialize_from(reader).context("Could not read checkpoint")?;
+
+    info!(size = checkpoint.builder.size(), "Read checkpoint");
+
+    Ok(checkpoint)
+}
+
+fn write_checkpoint<P: DataPoint>(
+    path: &Path,
+    checkpoint: &Checkpoint<&Builders<P>>,
+) -> Result<()> {
+    write_atomically(path, |writer| {
+        write_header::<P>(writer)?;
+        serialize_into(writer, checkpoint)?;
+        Ok(())
+    })
+}
+
 fn format_size_string(size: usize) -> String {
     match size {
         90_000..=110_000 => String::from("100K"),
@@ -259,23 +796,51 @@ fn format_size_string(size: usize) -> String {
 }
 
 #[instrument(skip_all)]
-fn write_result(
+fn write_result<P: DataPoint>(
     path: &impl AsRef<Path>,
-    results: Vec<Vec<Distance<'_, Sketch>>>,
+    results: Vec<Vec<Distance<'_, P>>>,
     k: usize,
     sort: bool,
     attrs: ResultAttrs,
 ) -> Result<()> {
     info!(path = path.as_ref().to_str(), ?sort, "Writing result");
-    let knns = BufferedDataset::create(path, (results.len(), k), "knns")?;
+    let file = Hdf5File::create(path)?;
+    let knns =
+        BufferedDataset::<'_, Array1<u64>, u64>::with_file(&file, (results.len(), k), "knns")?;
+    let dists = BufferedDataset::<'_, Array1<P::Dist>, P::Dist>::with_file(
+        &file,
+        (results.len(), k),
+        "dists",
+    )?;
 
     for (i, mut res) in results.into_iter().enumerate() {
         if sort {
             res.sort();
         }
 
-        let v = arr1(&res.iter().map(|d| d.key as u64 + 1).collect::<Vec<u64>>());
-        knns.write_row(v, i)?;
+        // Rows of searches that found fewer than `k` neighbors are padded as in ground truth files
+        if res.len() < k {
+            warn!(
+                query = i,
+                search = res.len(),
+                k,
+                "search returned fewer than k elements"
+            );
+        }
+        let (nn, dist): (Vec<_>, Vec<_>) = res
+            .iter()
+            .map(|d| {
+                (
+                    (d.key.into_usize() + attrs.start) as u64 + 1,
+                    P::dist(d.distance),
+                )
+            })
+            .chain(repeat((0, P::MAX_DIST)))
+            .take(k)
+            .unzip();
+
+        knns.write_row(arr1(&nn), i)?;
+        dists.write_row(arr1(&dist), i)?;
     }
 
     let size = if attrs.format_size {
@@ -311,24 +876,36 @@ fn write_result(
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct ResultAttrs {
     format_size: bool,
+    // First dataset row of the index, keys are relative to this row
+    start: usize,
     data: String,
     size: usize,
+    // Number of elements of every point, which queries must have as well
+    dim: Option<usize>,
     algo: Algorithm,
     buildtime: f64,
     querytime: f64,
     params: String,
+    // Beamwidth chosen by `tune`, used when querying without `-e`
+    ef: Option<usize>,
+    // Random id of the file as last written, which the log of changes to it starts with
+    snapshot: u64,
 }
 
 impl Default for ResultAttrs {
     fn default() -> Self {
         Self {
             format_size: true,
+            start: Default::default(),
             data: String::from("hamming"),
             size: Default::default(),
+            dim: None,
             algo: Default::default(),
             buildtime: Default::default(),
             querytime: Default::default(),
             params: String::from(""),
+            ef: None,
+            snapshot: 0,
         }
     }
 }
@@ -348,13 +925,151 @@ trait Action {
     fn act(self) -> Result<()>;
 }
 
+// Actions that are generic over the type of points in the dataset, which read index files through
+// `registry`
+trait PointAction {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Metric {
+    Hamming,
+    Euclidean,
+    Cosine,
+    InnerProduct,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Dtype {
+    U64,
+    F32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum PointType {
+    Sketch,
+    Euclidean,
+    Cosine,
+    InnerProduct,
+}
+
+impl PointType {
+    fn new(dtype: Dtype, metric: Metric) -> Result<Self> {
+        Ok(match (dtype, metric) {
+            (Dtype::U64, Metric::Hamming) => Self::Sketch,
+            (Dtype::F32, Metric::Euclidean) => Self::Euclidean,
+            (Dtype::F32, Metric::Cosine) => Self::Cosine,
+            (Dtype::F32, Metric::InnerProduct) => Self::InnerProduct,
+            _ => bail!("{metric:?} distance is not supported for {dtype:?} datasets"),
+        })
+    }
+
+    fn dispatch(self, action: impl PointAction) -> Result<()> {
+        match self {
+            Self::Sketch => action.run(&Registry::<Sketch>::builtin()),
+            Self::Euclidean => action.run(&Registry::<Vector<Euclidean>>::builtin()),
+            Self::Cosine => action.run(&Registry::<Vector<Cosine>>::builtin()),
+            Self::InnerProduct => action.run(&Registry::<Vector<InnerProduct>>::builtin()),
+        }
+    }
+}
+
+trait DataPoint:
+    Point
+    + Centroid
+    + Clone
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + From<Array1<Self::Element>>
+    + 'static
+{
+    type Element: Element + DeserializeOwned;
+    // Distance as written to result files
+    type Dist: H5Type + Clone + Serialize;
+
+    const TYPE: PointType;
+    // Number of elements in each row of raw files, if points have a fixed size
+    const RAW_DIM: Option<usize>;
+    // Distance used to pad rows of result files
+    const MAX_DIST: Self::Dist;
+
+    fn dist(distance: usize) -> Self::Dist;
+
+    // Number of elements of the point
+    fn dim(&self) -> usize;
+
+    // Inverse of `dist` for distances read from result files
+    fn key(dist: f64) -> usize;
+}
+
+impl DataPoint for Sketch {
+    type Element = u64;
+    type Dist = u64;
+
+    const TYPE: PointType = PointType::Sketch;
+    const RAW_DIM: Option<usize> = Some(Sketch::WORDS);
+    const MAX_DIST: Self::Dist = u64::MAX;
+
+    fn dist(distance: usize) -> Self::Dist {
+        distance as u64
+    }
+
+    fn dim(&self) -> usize {
+        Sketch::WORDS
+    }
+
+    fn key(dist: f64) -> usize {
+        dist as usize
+    }
+}
+
+macro_rules! vector_point {
+    ($metric:ident) => {
+        impl DataPoint for Vector<$metric> {
+            type Element = f32;
+            type Dist = f32;
+
+            const TYPE: PointType = PointType::$metric;
+            const RAW_DIM: Option<usize> = None;
+            const MAX_DIST: Self::Dist = f32::INFINITY;
+
+            fn dist(distance: usize) -> Self::Dist {
+                distance_from_key(distance)
+            }
+
+            fn dim(&self) -> usize {
+                self.data.len()
+            }
+
+            fn key(dist: f64) -> usize {
+                distance_key(dist as f32)
+            }
+        }
+    };
+}
+
+vector_point!(Euclidean);
+vector_point!(Cosine);
+vector_point!(InnerProduct);
+
 #[derive(Subcommand)]
 enum Commands {
     Query(Query),
     Index(CreateIndex),
     QueryIndex(QueryIndex),
+    QueryStream(QueryStream),
     GroundTruth(GroundTruth),
+    Evaluate(Evaluate),
+    Sweep(Sweep),
+    Tune(Tune),
+    Update(Update),
+    Compact(Compact),
+    Sketch(CreateSketches),
+    Serve(Serve),
     Inspect(Inspect),
+    Verify(Verify),
 }
 
 impl Commands {
@@ -363,8 +1078,17 @@ impl Commands {
             Self::Query(a) => a.act(),
             Self::Index(a) => a.act(),
             Self::QueryIndex(a) => a.act(),
+            Self::QueryStream(a) => a.act(),
             Self::GroundTruth(a) => a.act(),
+            Self::Evaluate(a) => a.act(),
+            Self::Sweep(a) => a.act(),
+            Self::Tune(a) => a.act(),
+            Self::Update(a) => a.act(),
+            Self::Compact(a) => a.act(),
+            Self::Sketch(a) => a.act(),
+            Self::Serve(a) => a.act(),
             Self::Inspect(a) => a.act(),
+            Self::Verify(a) => a.act(),
         }
     }
 }
@@ -374,8 +1098,14 @@ struct AlgorithmOptions {
     ef_construction: usize,
     connections: usize,
     max_connections: usize,
+    nlist: usize,
     single_threaded: bool,
     size: Option<usize>,
+    // Points to insert into graph indexes between checkpoints written to `checkpoint`
+    checkpoint_every: Option<usize>,
+    checkpoint: Option<PathBuf>,
+    // Checkpoint to continue the build from
+    resume: Option<PathBuf>,
 }
 
 #[derive(
@@ -386,114 +1116,587 @@ enum Algorithm {
     Bruteforce,
     Nsw,
     Hnsw,
+    Ivf,
 }
 
 impl Algorithm {
-    fn create<P: Point + Clone + Send + Sync>(
+    fn create<P: DataPoint>(
         &self,
         dataset: impl IntoIterator<Item = P>,
         options: impl Into<AlgorithmOptions>,
-    ) -> Indexes<P> {
+    ) -> Result<Box<dyn StoredIndex<P>>> {
         let options = options.into();
-        match self {
+
+        if let Some(mut builder) = self.builder(&options)? {
+            builder.try_extend(dataset, options.single_threaded)?;
+            return Ok(builder.build());
+        }
+
+        Ok(match self {
             Self::Bruteforce => {
-                let bruteforce = dataset.into_iter().collect();
-                Indexes::Bruteforce(bruteforce)
+                let bruteforce: Bruteforce<P> = dataset.into_iter().collect();
+                Box::new(bruteforce)
             }
-            Self::Nsw => {
-                let iter = dataset.into_iter();
-                let mut builder = NSWBuilder::new(NSWOptions {
-                    ef_construction: options.ef_construction,
-                    connections: options.connections,
-                    max_connections: options.max_connections,
-                    size: options.size.expect("size must be know"),
+            Self::Ivf => {
+                let mut builder = IVFBuilder::new(IVFOptions {
+                    nlist: options.nlist,
+                    ..IVFOptions::default()
                 });
 
-                if options.single_threaded {
-                    builder.extend(iter)
-                } else {
-                    builder.extend_parallel(iter);
-                }
+                builder.extend(dataset);
 
-                Indexes::NSW(builder.build())
+                Box::new(builder.build())
             }
-            Algorithm::Hnsw => {
-                let iter = dataset.into_iter();
-                let mut builder = HNSWBuilder::new(NSWOptions {
-                    ef_construction: options.ef_construction,
-                    connections: options.connections,
-                    max_connections: options.max_connections,
-                    size: options.size.expect("size must be know"),
-                });
+            Self::Nsw | Self::Hnsw => unreachable!("graph indexes are built by their builder"),
+        })
+    }
 
-                if options.single_threaded {
-                    builder.extend(iter)
-                } else {
-                    builder.extend_parallel(iter);
-                }
+    // Builder of a graph index, which is built point by point and can be checkpointed
+    fn builder<P>(&self, options: &AlgorithmOptions) -> Result<Option<Builders<P>>> {
+        let nsw_options = || -> Result<_> {
+            Ok(NSWOptions {
+                ef_construction: options.ef_construction,
+                connections: options.connections,
+                max_connections: options.max_connections,
+                size: options.size.ok_or(hnsw_itu::Error::InvalidParameter {
+                    name: "size",
+                    reason: "must be known",
+                })?,
+                ..NSWOptions::default()
+            })
+        };
 
-                Indexes::HNSW(builder.build())
-            }
-        }
+        Ok(match self {
+            Self::Nsw => Some(Builders::Nsw(NSWBuilder::new(nsw_options()?))),
+            Self::Hnsw => Some(Builders::Hnsw(Box::new(HNSWBuilder::new(nsw_options()?)))),
+            Self::Bruteforce | Self::Ivf => None,
+        })
     }
 }
 
 #[derive(Serialize, Deserialize)]
-pub enum Indexes<P> {
-    Bruteforce(Bruteforce<P>),
-    NSW(NSW<P>),
-    HNSW(HNSW<P>),
+enum Builders<P> {
+    Nsw(NSWBuilder<P>),
+    Hnsw(Box<HNSWBuilder<P>>),
 }
 
-impl<P> Index<P> for Indexes<P> {
+impl<P: DataPoint> Builders<P> {
+    // Number of points inserted so far
     fn size(&self) -> usize {
         match self {
-            Self::Bruteforce(bruteforce) => bruteforce.size(),
-            Self::NSW(nsw) => nsw.size(),
-            Self::HNSW(hnsw) => hnsw.size(),
+            Self::Nsw(builder) => builder.size(),
+            Self::Hnsw(builder) => builder.size(),
         }
     }
 
-    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
-    where
-        P: Point,
-    {
-        let mut res = match self {
-            Self::Bruteforce(bruteforce) => bruteforce.search(query, k, ef),
-            Self::NSW(nsw) => nsw.search(query, k, ef),
-            Self::HNSW(hnsw) => hnsw.search(query, k, ef),
-        };
-
-        if res.len() < k {
-            warn!(
-                search = res.len(),
-                k, "search returned fewer than k elements"
-            );
-            let fst = res.first().unwrap().clone();
-            res.extend(repeat(fst).take(k - res.len()));
+    fn try_extend(
+        &mut self,
+        dataset: impl IntoIterator<Item = P>,
+        single_threaded: bool,
+    ) -> Result<()> {
+        match self {
+            Self::Nsw(builder) if single_threaded => {
+                for point in dataset {
+                    builder.try_add(point)?;
+                }
+            }
+            Self::Nsw(builder) => builder.try_extend_parallel(dataset)?,
+            Self::Hnsw(builder) if single_threaded => {
+                for point in dataset {
+                    builder.try_add(point)?;
+                }
+            }
+            Self::Hnsw(builder) => builder.try_extend_parallel(dataset)?,
         }
 
-        res
+        Ok(())
     }
-}
 
-#[derive(Serialize, Deserialize)]
-struct IndexFile<P> {
-    attrs: ResultAttrs,
-    index: Indexes<P>,
+    fn set_progress(&mut self, progress: Option<ProgressFn>) {
+        match self {
+            Self::Nsw(builder) => builder.set_progress(progress),
+            Self::Hnsw(builder) => builder.set_progress(progress),
+        }
+    }
+
+    fn build(self) -> Box<dyn StoredIndex<P>> {
+        match self {
+            Self::Nsw(builder) => Box::new(builder.build()),
+            Self::Hnsw(builder) => Box::new(builder.build()),
+        }
+    }
+}
+
+// Index of any kind as kept in an index file, which commands use through `DynIndex` without
+// knowing its kind. Every index is written after its tag, by which `Registry` reads it back.
+trait StoredIndex<P>: DynIndex<P> {
+    fn tag(&self) -> &'static str;
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()>;
+
+    // Broken invariants of the graphs in the index, with the first row of the shard they are in
+    fn violations(&self, _max_connections: Option<usize>) -> Vec<(usize, Violation)> {
+        vec![]
+    }
+
+    // Connect the nodes of the graphs that are not reachable from their entry point, returning the
+    // number of edges added
+    fn repair(&mut self, _options: &NSWOptions) -> Result<usize> {
+        Ok(0)
+    }
+
+    // Insert points after those of the index, connected with the parameters of `options` in
+    // graphs, returning the points that this kind of index can not insert
+    fn insert(&mut self, points: Vec<P>, _options: &NSWOptions) -> Result<Vec<P>> {
+        Ok(points)
+    }
+
+    // Print the shape of the index
+    fn inspect(&self) {}
+
+    // The index as changed after it was built, if it has been
+    fn as_live(&self) -> Option<&LiveIndex<Box<dyn StoredIndex<P>>, P>> {
+        None
+    }
+
+    fn as_live_mut(&mut self) -> Option<&mut LiveIndex<Box<dyn StoredIndex<P>>, P>> {
+        None
+    }
+}
+
+impl<P: DataPoint> Index<P> for Box<dyn StoredIndex<P>> {
+    fn size(&self) -> usize {
+        (**self).dyn_size()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        (**self).dyn_search(query, k, ef)
+    }
+
+    fn try_search<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+    ) -> hnsw_itu::Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        (**self).dyn_try_search(query, k, ef)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> hnsw_itu::Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        (**self).dyn_try_search_skipping(query, k, ef, skip)
+    }
+
+    fn knns<I>(&self, queries: I, k: usize, ef: usize) -> Vec<Vec<Distance<'_, P>>>
+    where
+        Self: Sync,
+        I: IntoIterator<Item = P>,
+        P: Point + Sync,
+    {
+        (**self).dyn_knns(queries.into_iter().collect(), k, ef)
+    }
+}
+
+fn write_stored<P>(writer: &mut dyn Write, index: &dyn StoredIndex<P>) -> Result<()> {
+    serialize_into(&mut *writer, index.tag())?;
+    index.write(writer)
+}
+
+type ReadFn<P> = fn(&mut dyn Read, &Registry<P>) -> Result<Box<dyn StoredIndex<P>>>;
+
+// Kinds of indexes that can be read from index files, by their tag. Indexes that hold other
+// indexes read them through the registry as well. Commands are given the registry to read index
+// files with, such that kinds can be added by registering them with it before.
+#[derive(Clone)]
+struct Registry<P> {
+    kinds: HashMap<&'static str, ReadFn<P>>,
+}
+
+impl<P: DataPoint> Registry<P> {
+    // Registry without any kinds of indexes
+    fn new() -> Self {
+        Self {
+            kinds: HashMap::new(),
+        }
+    }
+
+    // Registry with the kinds of indexes that the `index` command builds and the indexes holding
+    // them
+    fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register("bruteforce", |reader, _| {
+            read_serialized::<_, Bruteforce<P>>(reader)
+        });
+        registry.register("nsw", |reader, _| read_serialized::<_, NSW<P>>(reader));
+        registry.register("hnsw", |reader, _| read_serialized::<_, HNSW<P>>(reader));
+        registry.register("ivf", |reader, _| read_serialized::<_, IVF<P>>(reader));
+        registry.register("sharded", read_sharded);
+        registry.register("live", read_live);
+        registry
+    }
+
+    fn register(&mut self, tag: &'static str, read: ReadFn<P>) {
+        self.kinds.insert(tag, read);
+    }
+
+    fn read(&self, reader: &mut dyn Read) -> Result<Box<dyn StoredIndex<P>>> {
+        let tag: String = deserialize_from(&mut *reader).context("Could not read index kind")?;
+        let read = self
+            .kinds
+            .get(tag.as_str())
+            .with_context(|| format!("Unknown kind of index {tag:?}"))?;
+        read(reader, self)
+    }
+}
+
+fn read_serialized<P, I: StoredIndex<P> + DeserializeOwned + 'static>(
+    reader: &mut dyn Read,
+) -> Result<Box<dyn StoredIndex<P>>> {
+    let index: I = deserialize_from(reader)?;
+    Ok(Box::new(index))
+}
+
+fn read_sharded<P: DataPoint>(
+    reader: &mut dyn Read,
+    registry: &Registry<P>,
+) -> Result<Box<dyn StoredIndex<P>>> {
+    let shards: usize = deserialize_from(&mut *reader)?;
+    let mut sharded = ShardedIndex::new();
+    for _ in 0..shards {
+        let offset = deserialize_from(&mut *reader)?;
+        sharded.add_shard(offset, registry.read(reader)?);
+    }
+
+    Ok(Box::new(sharded))
+}
+
+fn read_live<P: DataPoint>(
+    reader: &mut dyn Read,
+    registry: &Registry<P>,
+) -> Result<Box<dyn StoredIndex<P>>> {
+    let index = registry.read(reader)?;
+    let (added, removed, generation) = deserialize_from(reader)?;
+    Ok(Box::new(LiveIndex::with_changes(
+        index, added, removed, generation,
+    )))
+}
+
+impl<P: DataPoint> StoredIndex<P> for Bruteforce<P> {
+    fn tag(&self) -> &'static str {
+        "bruteforce"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        Ok(serialize_into(writer, self)?)
+    }
+
+    fn insert(&mut self, points: Vec<P>, _options: &NSWOptions) -> Result<Vec<P>> {
+        self.extend(points);
+        Ok(vec![])
+    }
+}
+
+impl<P: DataPoint> StoredIndex<P> for NSW<P> {
+    fn tag(&self) -> &'static str {
+        "nsw"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        Ok(serialize_into(writer, self)?)
+    }
+
+    fn violations(&self, max_connections: Option<usize>) -> Vec<(usize, Violation)> {
+        self.verify(max_connections)
+            .into_iter()
+            .map(|v| (0, v))
+            .collect()
+    }
+
+    fn repair(&mut self, options: &NSWOptions) -> Result<usize> {
+        Ok(NSW::repair(self, options)?)
+    }
+
+    fn insert(&mut self, points: Vec<P>, options: &NSWOptions) -> Result<Vec<P>> {
+        NSW::insert(self, points, options)?;
+        Ok(vec![])
+    }
+
+    fn inspect(&self) {
+        let graph = self.graph();
+        print_layer("base".to_string(), graph);
+        print_search_all(self, graph.get(0));
+    }
+}
+
+impl<P: DataPoint> StoredIndex<P> for HNSW<P> {
+    fn tag(&self) -> &'static str {
+        "hnsw"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        Ok(serialize_into(writer, self)?)
+    }
+
+    fn violations(&self, max_connections: Option<usize>) -> Vec<(usize, Violation)> {
+        self.verify(max_connections)
+            .into_iter()
+            .map(|v| (0, v))
+            .collect()
+    }
+
+    fn repair(&mut self, options: &NSWOptions) -> Result<usize> {
+        Ok(HNSW::repair(self, options)?)
+    }
+
+    fn insert(&mut self, points: Vec<P>, options: &NSWOptions) -> Result<Vec<P>> {
+        HNSW::insert(self, points, options)?;
+        Ok(vec![])
+    }
+
+    fn inspect(&self) {
+        for (i, layer) in self.layers().iter().enumerate().rev() {
+            print_layer(format!("layer{i}"), layer);
+        }
+        let base = self.base();
+        print_layer("base".to_string(), base);
+        print_search_all(self, base.get(0));
+    }
+}
+
+impl<P: DataPoint> StoredIndex<P> for IVF<P> {
+    fn tag(&self) -> &'static str {
+        "ivf"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        Ok(serialize_into(writer, self)?)
+    }
+
+    fn inspect(&self) {
+        let mut lists = self.lists().iter().map(Vec::len).collect::<Vec<_>>();
+        lists.sort();
+        println!("\n{} lists over {} elements", lists.len(), self.size());
+        if lists.is_empty() {
+            return;
+        }
+
+        let len = lists.len();
+        println!("list size distribution:");
+        for i in 0..11 {
+            println!("p{} {}", i * 10, lists[(len - 1).min(len / 10 * i)]);
+        }
+    }
+}
+
+impl<P: DataPoint> StoredIndex<P> for ShardedIndex<Box<dyn StoredIndex<P>>> {
+    fn tag(&self) -> &'static str {
+        "sharded"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        serialize_into(&mut *writer, &self.shards().len())?;
+        for (offset, shard) in self.shards() {
+            serialize_into(&mut *writer, offset)?;
+            write_stored(writer, shard.as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn violations(&self, max_connections: Option<usize>) -> Vec<(usize, Violation)> {
+        self.shards()
+            .iter()
+            .flat_map(|(offset, shard)| {
+                shard
+                    .violations(max_connections)
+                    .into_iter()
+                    .map(move |(start, v)| (offset + start, v))
+            })
+            .collect()
+    }
+
+    fn repair(&mut self, options: &NSWOptions) -> Result<usize> {
+        self.shards_mut().map(|shard| shard.repair(options)).sum()
+    }
+
+    fn inspect(&self) {
+        println!(
+            "\n{} shards over {} elements",
+            self.shards().len(),
+            self.size()
+        );
+        for (offset, shard) in self.shards() {
+            println!("offset {offset} size {}", shard.size());
+        }
+    }
+}
+
+impl<P: DataPoint> StoredIndex<P> for LiveIndex<Box<dyn StoredIndex<P>>, P> {
+    fn tag(&self) -> &'static str {
+        "live"
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        write_stored(writer, self.index().as_ref())?;
+        serialize_into(writer, &(self.added(), self.removed(), self.generation()))?;
+        Ok(())
+    }
+
+    fn violations(&self, max_connections: Option<usize>) -> Vec<(usize, Violation)> {
+        self.index().violations(max_connections)
+    }
+
+    fn repair(&mut self, options: &NSWOptions) -> Result<usize> {
+        self.index_mut().repair(options)
+    }
+
+    fn inspect(&self) {
+        println!(
+            "\n{} added and {} removed points at generation {} over {} indexed points",
+            self.added().len(),
+            self.removed().len(),
+            self.generation(),
+            self.index().size()
+        );
+    }
+
+    fn as_live(&self) -> Option<&LiveIndex<Box<dyn StoredIndex<P>>, P>> {
+        Some(self)
+    }
+
+    fn as_live_mut(&mut self) -> Option<&mut LiveIndex<Box<dyn StoredIndex<P>>, P>> {
+        Some(self)
+    }
+}
+
+fn print_layer<T>(name: String, layer: &SimpleGraph<T>) {
+    let node_count = layer.nodes().len();
+    let mut connections = layer
+        .adj_lists()
+        .iter()
+        .map(|l| l.len())
+        .collect::<Vec<_>>();
+    connections.sort();
+    if node_count == 0 {
+        println!("\n{name} has no nodes");
+        return;
+    }
+    let total_connections: usize = connections.iter().sum();
+    let avg_connections = total_connections / node_count;
+    println!("\n{name} has {node_count} nodes, {total_connections} total connections, and {avg_connections} average connections");
+
+    let len = connections.len();
+    println!("connection distribution:");
+    for i in 0..11 {
+        println!("p{} {}", i * 10, connections[(len - 1).min(len / 10 * i)]);
+    }
+}
+
+// Search a graph index for all of its points from one of them
+fn print_search_all<P: Point>(index: &impl Index<P>, point: Option<&P>) {
+    let Some(point) = point else {
+        return;
+    };
+
+    let size = index.size();
+    let res = index.search(point, size, size);
+    println!(
+        "\nquery on whole index returned {}/{} elements",
+        res.len(),
+        size
+    );
+}
+
+struct IndexFile<P> {
+    attrs: ResultAttrs,
+    index: Box<dyn StoredIndex<P>>,
+}
+
+impl<P: DataPoint> IndexFile<P> {
+    fn read(reader: &mut dyn Read, registry: &Registry<P>) -> Result<Self> {
+        let attrs = deserialize_from(&mut *reader)?;
+        let index = registry.read(reader)?;
+        Ok(Self { attrs, index })
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        serialize_into(&mut *writer, &self.attrs)?;
+        write_stored(writer, self.index.as_ref())
+    }
+
+    // Index that changes are made to, which the index becomes the first time it is changed
+    fn live(&mut self) -> &mut LiveIndex<Box<dyn StoredIndex<P>>, P> {
+        if self.index.as_live().is_none() {
+            let index = std::mem::replace(&mut self.index, Box::new(Bruteforce::new()));
+            self.index = Box::new(LiveIndex::new(index));
+        }
+
+        self.index.as_live_mut().expect("index was made live")
+    }
+}
+
+// Searches the index, such that the attributes of the file are at hand for the results
+impl<P: DataPoint> Index<P> for IndexFile<P> {
+    fn size(&self) -> usize {
+        self.index.size()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        self.index.search(query, k, ef)
+    }
+
+    fn try_search<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+    ) -> hnsw_itu::Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.index.try_search(query, k, ef)
+    }
 }
 
 /// Create index from dataset, query it and generate result file
 #[derive(Args, Debug)]
 struct Query {
-    /// HDF5 file with binary sketches
+    /// File with the dataset (.h5, .hdf5, .fvecs, .npy, .bin or .raw)
     #[arg(short, long)]
     datafile: PathBuf,
 
-    /// HDF5 file with queries into the dataset
+    /// File with queries into the dataset, in any format supported for the dataset
     #[arg(short = 'Q', long)]
     queryfile: PathBuf,
 
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Distance metric between points
+    #[arg(long, value_enum, default_value_t = Metric::Hamming)]
+    metric: Metric,
+
+    /// Element type of the dataset, u64 for binary sketches and f32 for float vectors
+    #[arg(long, value_enum, default_value_t = Dtype::U64)]
+    dtype: Dtype,
+
     /// Location of resulting file
     #[arg(short, long, default_value_t = String::from("result.h5"))]
     outfile: String,
@@ -506,7 +1709,7 @@ struct Query {
     #[arg(short, default_value_t = 10)]
     k: usize,
 
-    /// Beamwidth during search
+    /// Beamwidth during search, or number of lists to probe for IVF
     #[arg(short = 'e', default_value_t = 96)]
     ef: usize,
 
@@ -522,6 +1725,10 @@ struct Query {
     #[arg(short = 'M', default_value_t = 256)]
     max_connections: usize,
 
+    /// Number of lists (clusters) for IVF
+    #[arg(long, default_value_t = 1024)]
+    nlist: usize,
+
     /// What algorithm to use for index construction
     #[arg(short, long, value_enum, default_value_t = Algorithm::Hnsw)]
     algorithm: Algorithm,
@@ -533,6 +1740,27 @@ struct Query {
     /// Do all querying on a single thread
     #[arg(short = 'S', long, default_value_t = false)]
     single_threaded: bool,
+
+    /// File with the original float vectors of the dataset. Candidates found by the index are then
+    /// re-ranked by the exact distance between float vectors.
+    #[arg(long, requires = "rerank_queryfile")]
+    rerank_file: Option<PathBuf>,
+
+    /// File with the original float vectors of the queries
+    #[arg(long, requires = "rerank_file")]
+    rerank_queryfile: Option<PathBuf>,
+
+    /// Name of the datasets to read from HDF5 rerank files
+    #[arg(long, default_value_t = String::from("emb"))]
+    rerank_name: String,
+
+    /// Distance metric between float vectors when re-ranking
+    #[arg(long, value_enum, default_value_t = Metric::Cosine)]
+    rerank_metric: Metric,
+
+    /// Number of candidates fetched from the index for every neighbor to find when re-ranking
+    #[arg(long, default_value_t = 10)]
+    rerank_factor: usize,
 }
 
 impl From<&Query> for AlgorithmOptions {
@@ -541,22 +1769,44 @@ impl From<&Query> for AlgorithmOptions {
             connections: value.connections,
             ef_construction: value.ef_construction,
             max_connections: value.max_connections,
+            nlist: value.nlist,
             single_threaded: value.single_threaded,
-            size: None,
+            ..Default::default()
         }
     }
 }
 
 impl Action for Query {
     fn act(self) -> Result<()> {
-        let mut index_file = build_index(&self.datafile, self.algorithm, &self, None, None)?;
+        PointType::new(self.dtype, self.metric)?.dispatch(self)
+    }
+}
+
+impl PointAction for Query {
+    fn run<P: DataPoint>(self, _registry: &Registry<P>) -> Result<()> {
+        let mut index_file = build_index::<P>(
+            &self.datafile,
+            &self.dataset_name,
+            self.algorithm,
+            &self,
+            None,
+            None,
+        )?;
+
+        if let Some(path) = &self.indexfile {
+            write_index(path, &mut index_file)?;
+        }
 
-        if let Some(path) = self.indexfile {
-            write_index(&path, &index_file)?;
+        if self.rerank_file.is_some() {
+            return PointType::new(Dtype::F32, self.rerank_metric)?.dispatch(RerankQuery {
+                query: self,
+                index_file,
+            });
         }
 
         let results = query_index(
             &self.queryfile,
+            &self.dataset_name,
             &index_file.index,
             &mut index_file.attrs,
             self.k,
@@ -570,13 +1820,102 @@ impl Action for Query {
     }
 }
 
+// Query an index of `P` and re-rank the candidates by the exact distance between points of type `V`
+struct RerankQuery<P> {
+    query: Query,
+    index_file: IndexFile<P>,
+}
+
+impl<P: DataPoint> PointAction for RerankQuery<P> {
+    #[instrument(name = "rerank_query", skip_all)]
+    fn run<V: DataPoint>(self, _registry: &Registry<V>) -> Result<()> {
+        let Self {
+            query,
+            index_file: IndexFile { mut attrs, index },
+        } = self;
+        let (Some(datafile), Some(queryfile)) = (&query.rerank_file, &query.rerank_queryfile)
+        else {
+            bail!("re-ranking needs both --rerank-file and --rerank-queryfile");
+        };
+        let (k, ef, factor) = (query.k, query.ef, query.rerank_factor);
+
+        let (points, size) = read_dataset::<V>(datafile, &query.rerank_name, None, None)?;
+        if size != index.size() {
+            bail!(
+                "{datafile:?} holds {size} points but the index holds {}",
+                index.size()
+            );
+        }
+        let rerank = Rerank::new(index, points.collect(), factor)?;
+
+        let queries = open_points::<P>(&query.queryfile, &query.dataset_name)?;
+        let exact = open_points::<V>(queryfile, &query.rerank_name)?;
+        if queries.size() != exact.size() {
+            bail!(
+                "{:?} holds {} queries but {queryfile:?} holds {}",
+                query.queryfile,
+                queries.size(),
+                exact.size()
+            );
+        }
+        let queries_size: u32 = queries.size().try_into().unwrap();
+
+        if k * factor > ef && attrs.algo != Algorithm::Ivf {
+            error!(
+                candidates = k * factor,
+                ef, "number of candidates is greater than `ef`, this can have adverse effects"
+            );
+        }
+
+        info!(
+            k,
+            ef,
+            factor,
+            single_threaded = query.single_threaded,
+            "Start querying"
+        );
+        let querytime_start = SystemTime::now();
+        let results = if query.single_threaded {
+            queries
+                .zip(exact)
+                .map(|(q, e)| rerank.search(&q, &e, k, ef))
+                .collect()
+        } else {
+            rerank.knns(queries.zip(exact), k, ef)
+        };
+        let querytime_total = querytime_start.elapsed().unwrap_or_default();
+        let querytime_per_element = querytime_total / queries_size;
+        info!(
+            "Total query time: {:?}, per query: {:?}",
+            querytime_total, querytime_per_element
+        );
+
+        attrs.querytime = querytime_total.as_secs_f64();
+        attrs.params = format!("{},query=(ef={ef},rerank={factor})", attrs.params);
+
+        write_result(&query.outfile, results, k, query.sort, attrs)
+    }
+}
+
 /// Index dataset and generate result file used for queries
 #[derive(Args, Debug)]
 struct CreateIndex {
-    /// HDF5 file with binary sketches
+    /// File with the dataset (.h5, .hdf5, .fvecs, .npy, .bin or .raw)
     #[arg(short, long)]
     datafile: PathBuf,
 
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Distance metric between points
+    #[arg(long, value_enum, default_value_t = Metric::Hamming)]
+    metric: Metric,
+
+    /// Element type of the dataset, u64 for binary sketches and f32 for float vectors
+    #[arg(long, value_enum, default_value_t = Dtype::U64)]
+    dtype: Dtype,
+
     /// Location of resulting file
     #[arg(short, long, default_value_t = String::from("index.idx"))]
     outfile: String,
@@ -593,6 +1932,10 @@ struct CreateIndex {
     #[arg(short = 'M', default_value_t = 256)]
     max_connections: usize,
 
+    /// Number of lists (clusters) for IVF
+    #[arg(long, default_value_t = 1024)]
+    nlist: usize,
+
     /// At what row in the datafile to start indexing
     #[arg(short = 'b', long)]
     start: Option<usize>,
@@ -608,6 +1951,24 @@ struct CreateIndex {
     /// Build index on a single thread. Doing so can result in better indexes.
     #[arg(short = 'S', long, default_value_t = false)]
     single_threaded: bool,
+
+    /// Write a checkpoint of the build to `<outfile>.ckpt` every N rows (nsw and hnsw only)
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<usize>,
+
+    /// Continue the build from a checkpoint, given the same dataset range and algorithm
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+
+    /// Connect nodes that are not reachable from the entry point after the build (nsw and hnsw only)
+    #[arg(long, default_value_t = false)]
+    repair: bool,
+}
+
+impl CreateIndex {
+    fn checkpoint_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.ckpt", self.outfile))
+    }
 }
 
 impl From<&CreateIndex> for AlgorithmOptions {
@@ -616,16 +1977,61 @@ impl From<&CreateIndex> for AlgorithmOptions {
             connections: value.connections,
             ef_construction: value.ef_construction,
             max_connections: value.max_connections,
+            nlist: value.nlist,
             single_threaded: value.single_threaded,
             size: None,
+            checkpoint_every: value.checkpoint_every,
+            checkpoint: value.checkpoint_every.map(|_| value.checkpoint_path()),
+            resume: value.resume.clone(),
         }
     }
 }
 
 impl Action for CreateIndex {
     fn act(self) -> Result<()> {
-        let index = build_index(&self.datafile, self.algorithm, &self, self.start, self.len)?;
-        write_index(&self.outfile, &index)?;
+        PointType::new(self.dtype, self.metric)?.dispatch(self)
+    }
+}
+
+impl PointAction for CreateIndex {
+    fn run<P: DataPoint>(self, _registry: &Registry<P>) -> Result<()> {
+        if self.checkpoint_every == Some(0) {
+            bail!("--checkpoint-every must be greater than 0");
+        }
+        let graph = matches!(self.algorithm, Algorithm::Nsw | Algorithm::Hnsw);
+        if (self.checkpoint_every.is_some() || self.resume.is_some()) && !graph {
+            bail!("checkpoints are only supported by the nsw and hnsw algorithms");
+        }
+        if self.repair && !graph {
+            bail!("`--repair` is only supported by the nsw and hnsw algorithms");
+        }
+
+        let mut index = build_index::<P>(
+            &self.datafile,
+            &self.dataset_name,
+            self.algorithm,
+            &self,
+            self.start,
+            self.len,
+        )?;
+
+        // A resumed build has the parameters of the checkpoint, which the index was built with
+        if self.repair {
+            let options = graph_options(&index.attrs.params)
+                .context("Parameters of the index are unknown")?;
+            let added = index.index.repair(&options)?;
+            info!(added, "Connected unreachable nodes");
+        }
+
+        write_index(&self.outfile, &mut index)?;
+
+        // The checkpoint is of no use once the index is written
+        if self.checkpoint_every.is_some() {
+            match fs::remove_file(self.checkpoint_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)?,
+                _ => {}
+            }
+        }
 
         Ok(())
     }
@@ -634,14 +2040,19 @@ impl Action for CreateIndex {
 /// Query an index file generated by the `index` command and generate result file
 #[derive(Args, Debug)]
 struct QueryIndex {
-    /// Index file to query
-    #[arg(short, long)]
-    indexfile: PathBuf,
+    /// Index file to query. Multiple files are queried together as shards, each covering the
+    /// dataset rows it was indexed from.
+    #[arg(short, long, num_args = 1.., required = true)]
+    indexfile: Vec<PathBuf>,
 
-    /// HDF5 file with queries into the dataset
+    /// File with queries into the dataset, in any format supported for the dataset
     #[arg(short = 'Q', long)]
     queryfile: PathBuf,
 
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
     /// Location of resulting file
     #[arg(short, long, default_value_t = String::from("result.h5"))]
     outfile: String,
@@ -650,9 +2061,10 @@ struct QueryIndex {
     #[arg(short, default_value_t = 10)]
     k: usize,
 
-    /// Beamwidth during search
-    #[arg(short = 'e', default_value_t = 96)]
-    ef: usize,
+    /// Beamwidth during search, or number of lists to probe for IVF [default: the beamwidth
+    /// chosen by `tune`, otherwise 96]
+    #[arg(short = 'e')]
+    ef: Option<usize>,
 
     /// Put nearest neighbors in sorted (ascending) order
     #[arg(short, long, default_value_t = false)]
@@ -665,13 +2077,21 @@ struct QueryIndex {
 
 impl Action for QueryIndex {
     fn act(self) -> Result<()> {
-        let mut index_file = read_index(&self.indexfile)?;
+        read_point_type(&self.indexfile[0])?.dispatch(self)
+    }
+}
+
+impl PointAction for QueryIndex {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        let mut index_file = read_sharded_index(&self.indexfile, registry)?;
+        let ef = self.ef.or(index_file.attrs.ef).unwrap_or(96);
         let results = query_index(
             &self.queryfile,
+            &self.dataset_name,
             &index_file.index,
             &mut index_file.attrs,
             self.k,
-            self.ef,
+            ef,
             self.single_threaded,
         )?;
         write_result(&self.outfile, results, self.k, self.sort, index_file.attrs)?;
@@ -683,14 +2103,26 @@ impl Action for QueryIndex {
 /// Generate ground truth from a dataset given a set of queries
 #[derive(Args)]
 struct GroundTruth {
-    /// HDF5 file with binary sketches
+    /// File with the dataset (.h5, .hdf5, .fvecs, .npy, .bin or .raw)
     #[arg(short, long)]
     datafile: PathBuf,
 
-    /// HDF5 file with queries into the dataset
+    /// File with queries into the dataset, in any format supported for the dataset
     #[arg(short = 'Q', long)]
     queryfile: PathBuf,
 
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Distance metric between points
+    #[arg(long, value_enum, default_value_t = Metric::Hamming)]
+    metric: Metric,
+
+    /// Element type of the dataset, u64 for binary sketches and f32 for float vectors
+    #[arg(long, value_enum, default_value_t = Dtype::U64)]
+    dtype: Dtype,
+
     /// Location of resulting file
     #[arg(short, long, default_value_t = String::from("groundtruth.h5"))]
     outfile: String,
@@ -710,30 +2142,102 @@ struct GroundTruth {
     /// Put nearest neighbors in sorted (ascending) order
     #[arg(short, long, default_value_t = true)]
     sort: bool,
+
+    /// What exact algorithm to use for finding the nearest neighbors
+    #[arg(short, long, value_enum, default_value_t = GroundTruthAlgorithm::Bruteforce)]
+    algorithm: GroundTruthAlgorithm,
+
+    /// Number of substrings (hash tables) for MIH, must divide 1024 into at most 64 bits
+    #[arg(long, default_value_t = 32)]
+    substrings: usize,
+
+    /// Include all neighbors tied with the k-th nearest neighbor. Rows shorter than the longest
+    /// row are padded with id 0 and the maximum distance.
+    #[arg(short, long, default_value_t = false)]
+    ties: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum GroundTruthAlgorithm {
+    Bruteforce,
+    Mih,
 }
 
 impl Action for GroundTruth {
     fn act(self) -> Result<()> {
-        let mut index_file = build_index(
-            &self.datafile,
-            Algorithm::Bruteforce,
-            AlgorithmOptions::default(),
-            self.start,
-            self.len,
-        )?;
+        let point_type = PointType::new(self.dtype, self.metric)?;
+
+        match self.algorithm {
+            GroundTruthAlgorithm::Bruteforce => point_type.dispatch(self),
+            GroundTruthAlgorithm::Mih => {
+                if point_type != PointType::Sketch {
+                    bail!("MIH only supports binary sketches");
+                }
+                if self.ties {
+                    bail!("`--ties` is only supported by the bruteforce algorithm");
+                }
+
+                let mut mih = MIH::new(self.substrings)?;
+                let (dataset_iter, size) = read_dataset::<Sketch>(
+                    &self.datafile,
+                    &self.dataset_name,
+                    self.start,
+                    self.len,
+                )?;
+
+                info!(size, substrings = self.substrings, "Building MIH");
+                mih.extend(dataset_iter);
+                self.write(&mih)
+            }
+        }
+    }
+}
+
+impl PointAction for GroundTruth {
+    fn run<P: DataPoint>(self, _registry: &Registry<P>) -> Result<()> {
+        let (dataset_iter, size) =
+            read_dataset::<P>(&self.datafile, &self.dataset_name, self.start, self.len)?;
+
+        info!(size, ties = self.ties, "Building bruteforce");
+        let bruteforce = dataset_iter.collect::<Bruteforce<_>>().with_ties(self.ties);
+        self.write(&bruteforce)
+    }
+}
+
+impl GroundTruth {
+    fn write<P: DataPoint>(&self, index: &(impl Index<P> + Sync)) -> Result<()> {
         let results = query_index(
             &self.queryfile,
-            &index_file.index,
-            &mut index_file.attrs,
+            &self.dataset_name,
+            index,
+            &mut ResultAttrs::default(),
             self.k,
             self.k,
             false,
         )?;
 
-        info!(outfile = self.outfile, sort = self.sort, "Writing result");
-        let file = Hdf5File::create(self.outfile)?;
-        let knns = BufferedDataset::with_file(&file, (results.len(), self.k), "knns")?;
-        let dists = BufferedDataset::with_file(&file, (results.len(), self.k), "dists")?;
+        let start = self.start.unwrap_or_default();
+
+        // Rows can be longer than `k` when including ties
+        let width = results.iter().map(Vec::len).fold(self.k, usize::max);
+
+        info!(
+            outfile = self.outfile,
+            sort = self.sort,
+            width,
+            "Writing result"
+        );
+        let file = Hdf5File::create(&self.outfile)?;
+        let knns = BufferedDataset::<'_, Array1<u64>, u64>::with_file(
+            &file,
+            (results.len(), width),
+            "knns",
+        )?;
+        let dists = BufferedDataset::<'_, Array1<P::Dist>, P::Dist>::with_file(
+            &file,
+            (results.len(), width),
+            "dists",
+        )?;
 
         for (i, mut res) in results.into_iter().enumerate() {
             if self.sort {
@@ -742,7 +2246,9 @@ impl Action for GroundTruth {
 
             let (nn, dist): (Vec<_>, Vec<_>) = res
                 .iter()
-                .map(|d| (d.key as u64 + 1, d.distance as u64))
+                .map(|d| ((d.key.into_usize() + start) as u64 + 1, P::dist(d.distance)))
+                .chain(repeat((0, P::MAX_DIST)))
+                .take(width)
                 .unzip();
 
             knns.write_row(arr1(&nn), i)?;
@@ -753,6 +2259,1178 @@ impl Action for GroundTruth {
     }
 }
 
+/// Compute recall of a result file against a ground truth file
+#[derive(Args)]
+struct Evaluate {
+    /// Result file generated by `query` or `query-index`
+    #[arg(short, long)]
+    resultfile: PathBuf,
+
+    /// Ground truth file generated by `ground-truth`
+    #[arg(short, long)]
+    groundtruth: PathBuf,
+
+    /// Number of nearest neighbors to evaluate (default: all neighbors in the result file)
+    #[arg(short)]
+    k: Option<usize>,
+
+    /// How to print the evaluation
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Serialize, Debug)]
+struct Evaluation {
+    data: String,
+    size: String,
+    algo: String,
+    params: String,
+    buildtime: f64,
+    querytime: f64,
+    queries: usize,
+    k: usize,
+    recall: f64,
+    min_recall: f64,
+    // Only available when the result file contains distances
+    distance_ratio: Option<f64>,
+}
+
+impl Evaluation {
+    fn print(&self, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+            OutputFormat::Table => {
+                let distance_ratio = self
+                    .distance_ratio
+                    .map_or_else(|| String::from("N/A"), |r| format!("{r:.5}"));
+
+                for (name, value) in [
+                    ("data", self.data.clone()),
+                    ("size", self.size.clone()),
+                    ("algo", self.algo.clone()),
+                    ("params", self.params.clone()),
+                    ("buildtime", format!("{:.3}s", self.buildtime)),
+                    ("querytime", format!("{:.3}s", self.querytime)),
+                    ("queries", self.queries.to_string()),
+                    ("k", self.k.to_string()),
+                    ("recall", format!("{:.5}", self.recall)),
+                    ("min recall", format!("{:.5}", self.min_recall)),
+                    ("distance ratio", distance_ratio),
+                ] {
+                    println!("{name:<16}{value}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Action for Evaluate {
+    fn act(self) -> Result<()> {
+        info!(resultfile = ?self.resultfile, groundtruth = ?self.groundtruth, "Opening");
+        let results = BufferedDataset::<'_, Array1<u64>, u64>::open(&self.resultfile, "knns")?;
+        let truth = BufferedDataset::<'_, Array1<u64>, u64>::open(&self.groundtruth, "knns")?;
+        let truth_dists =
+            BufferedDataset::<'_, Array1<f64>, f64>::open(&self.groundtruth, "dists")?;
+        // Result files written before distances were included only contain `knns`
+        let result_dists =
+            BufferedDataset::<'_, Array1<f64>, f64>::open(&self.resultfile, "dists").ok();
+
+        let k = self.k.unwrap_or(results.dim());
+        if results.size() != truth.size() {
+            bail!(
+                "result file has {} queries but ground truth has {}",
+                results.size(),
+                truth.size()
+            );
+        }
+        if k > results.dim() || k > truth.dim() {
+            bail!(
+                "k = {k} is greater than the number of neighbors in the result file ({}) or ground truth ({})",
+                results.dim(),
+                truth.dim()
+            );
+        }
+
+        let text = |name| -> Result<String> {
+            Ok(results.attr::<VarLenUnicode>(name)?.as_str().to_owned())
+        };
+        let mut evaluation = Evaluation {
+            data: text("data")?,
+            size: text("size")?,
+            algo: text("algo")?,
+            params: text("params")?,
+            buildtime: results.attr("buildtime")?,
+            querytime: results.attr("querytime")?,
+            queries: results.size(),
+            k,
+            recall: 0.0,
+            min_recall: 0.0,
+            distance_ratio: None,
+        };
+
+        let to_usize = |row: Array1<u64>| row.iter().map(|&x| x as usize).collect::<Vec<_>>();
+        let mut result_dists = result_dists.map(IntoIterator::into_iter);
+        let mut recalls = vec![];
+        let mut ratios = vec![];
+
+        for ((res, gt), gt_dists) in results.into_iter().zip(truth).zip(truth_dists) {
+            let gt_dists = gt_dists.to_vec();
+            recalls.push(recall(&to_usize(res), &to_usize(gt), &gt_dists, k));
+
+            if let Some(dists) = result_dists.as_mut().and_then(Iterator::next) {
+                ratios.extend(distance_ratio(&dists.to_vec(), &gt_dists, k));
+            }
+        }
+
+        evaluation.recall = recalls.iter().sum::<f64>() / recalls.len().max(1) as f64;
+        evaluation.min_recall = recalls.iter().copied().fold(1.0, f64::min);
+        if result_dists.is_some() {
+            evaluation.distance_ratio =
+                Some(ratios.iter().sum::<f64>() / ratios.len().max(1) as f64);
+        }
+
+        evaluation.print(self.format)
+    }
+}
+
+/// Query an index with a range of parameters and report recall against throughput
+#[derive(Args)]
+struct Sweep {
+    /// Index file to query. Multiple files are queried together as shards.
+    #[arg(short, long, num_args = 1.., required = true)]
+    indexfile: Vec<PathBuf>,
+
+    /// File with queries into the dataset, in any format supported for the dataset
+    #[arg(short = 'Q', long)]
+    queryfile: PathBuf,
+
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Ground truth file generated by `ground-truth`
+    #[arg(short, long)]
+    groundtruth: PathBuf,
+
+    /// Comma separated beamwidths to query with, or number of lists to probe for IVF
+    #[arg(
+        short = 'e',
+        value_delimiter = ',',
+        default_value = "16,32,64,96,128,256"
+    )]
+    ef: Vec<usize>,
+
+    /// Comma separated numbers of nearest neighbors to find
+    #[arg(short, value_delimiter = ',', default_value = "10")]
+    k: Vec<usize>,
+
+    /// Location of resulting table, written as JSON if the extension is `.json` and CSV otherwise
+    #[arg(short, long, default_value_t = String::from("sweep.csv"))]
+    outfile: String,
+
+    /// Write every run instead of only the Pareto frontier of recall and QPS
+    #[arg(short, long, default_value_t = false)]
+    all: bool,
+
+    /// Do all querying on a single thread
+    #[arg(short = 'S', long, default_value_t = false)]
+    single_threaded: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct SweepRun {
+    k: usize,
+    ef: usize,
+    recall: f64,
+    min_recall: f64,
+    qps: f64,
+    // Latencies in milliseconds
+    mean_latency: f64,
+    p50_latency: f64,
+    p95_latency: f64,
+    p99_latency: f64,
+}
+
+impl SweepRun {
+    const CSV_HEADER: &'static str =
+        "k,ef,recall,min_recall,qps,mean_latency,p50_latency,p95_latency,p99_latency";
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{:.5},{:.5},{:.1},{:.4},{:.4},{:.4},{:.4}",
+            self.k,
+            self.ef,
+            self.recall,
+            self.min_recall,
+            self.qps,
+            self.mean_latency,
+            self.p50_latency,
+            self.p95_latency,
+            self.p99_latency
+        )
+    }
+}
+
+// Runs not beaten on both recall and QPS by any other run, ordered by decreasing recall
+fn pareto_frontier(mut runs: Vec<SweepRun>) -> Vec<SweepRun> {
+    runs.sort_by(|a, b| b.recall.total_cmp(&a.recall).then(b.qps.total_cmp(&a.qps)));
+
+    let mut best_qps = f64::NEG_INFINITY;
+    runs.retain(|run| {
+        let keep = run.qps > best_qps;
+        best_qps = best_qps.max(run.qps);
+        keep
+    });
+
+    runs
+}
+
+fn read_rows(path: &PathBuf, name: &str) -> Result<Vec<Vec<usize>>> {
+    let dataset = BufferedDataset::<'_, Array1<u64>, u64>::open(path, name)?;
+    Ok(dataset
+        .into_iter()
+        .map(|row| row.iter().map(|&x| x as usize).collect())
+        .collect())
+}
+
+// Distances are read as floats, which holds both sketch and float vector distances
+fn read_distances(path: &PathBuf) -> Result<Vec<Vec<f64>>> {
+    let dataset = BufferedDataset::<'_, Array1<f64>, f64>::open(path, "dists")?;
+    Ok(dataset.into_iter().map(|row| row.to_vec()).collect())
+}
+
+impl Action for Sweep {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile[0])?.dispatch(self)
+    }
+}
+
+impl PointAction for Sweep {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        let index_file = read_sharded_index(&self.indexfile, registry)?;
+        let index = &index_file.index;
+        let start = index_file.attrs.start;
+
+        let queries = open_points::<P>(&self.queryfile, &self.dataset_name)?.collect::<Vec<_>>();
+
+        info!(path = ?self.groundtruth, "Opening");
+        let truth = read_rows(&self.groundtruth, "knns")?;
+        let truth_dists = read_distances(&self.groundtruth)?;
+        let width = truth.first().map_or(0, Vec::len);
+
+        if truth.len() != queries.len() {
+            bail!(
+                "query file has {} queries but ground truth has {}",
+                queries.len(),
+                truth.len()
+            );
+        }
+        if let Some(k) = self.k.iter().find(|&&k| k > width) {
+            bail!("k = {k} is greater than the number of neighbors in the ground truth ({width})");
+        }
+
+        let mut runs = vec![];
+        for &k in &self.k {
+            for &ef in &self.ef {
+                let search = |query: &P| {
+                    let time = Instant::now();
+                    let res = index.search(query, k, ef);
+                    (res, time.elapsed())
+                };
+
+                let querytime_start = Instant::now();
+                let results: Vec<_> = if self.single_threaded {
+                    queries.iter().map(search).collect()
+                } else {
+                    queries.par_iter().map(search).collect()
+                };
+                let querytime_total = querytime_start.elapsed();
+
+                let recalls = results
+                    .iter()
+                    .zip(truth.iter().zip(&truth_dists))
+                    .map(|((res, _), (gt, gt_dists))| {
+                        // Ground truth ids are 1-indexed dataset rows
+                        let ids = res
+                            .iter()
+                            .map(|d| d.key.into_usize() + start + 1)
+                            .collect::<Vec<_>>();
+                        recall(&ids, gt, gt_dists, k)
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut latencies = results
+                    .iter()
+                    .map(|(_, latency)| latency.as_secs_f64() * 1000.0)
+                    .collect::<Vec<_>>();
+                latencies.sort_by(f64::total_cmp);
+                let percentile = |p: f64| {
+                    let i = ((latencies.len() as f64 - 1.0) * p).round() as usize;
+                    latencies.get(i).copied().unwrap_or_default()
+                };
+
+                let n = queries.len().max(1) as f64;
+                let run = SweepRun {
+                    k,
+                    ef,
+                    recall: recalls.iter().sum::<f64>() / n,
+                    min_recall: recalls.iter().copied().fold(1.0, f64::min),
+                    qps: queries.len() as f64 / querytime_total.as_secs_f64(),
+                    mean_latency: latencies.iter().sum::<f64>() / n,
+                    p50_latency: percentile(0.5),
+                    p95_latency: percentile(0.95),
+                    p99_latency: percentile(0.99),
+                };
+                info!(k, ef, recall = run.recall, qps = run.qps, "Finished run");
+                runs.push(run);
+            }
+        }
+
+        let runs = if self.all {
+            runs
+        } else {
+            pareto_frontier(runs)
+        };
+
+        info!(path = self.outfile, runs = runs.len(), "Writing sweep");
+        let contents = if self.outfile.ends_with(".json") {
+            serde_json::to_string_pretty(&runs)?
+        } else {
+            let mut csv = vec![SweepRun::CSV_HEADER.to_owned()];
+            csv.extend(runs.iter().map(SweepRun::to_csv));
+            csv.join("\n") + "\n"
+        };
+        fs::write(&self.outfile, contents)?;
+
+        Ok(())
+    }
+}
+
+/// Find the smallest beamwidth reaching a target recall and store it in the index file
+#[derive(Args)]
+struct Tune {
+    /// Index file generated by the `index` command
+    #[arg(short, long)]
+    indexfile: PathBuf,
+
+    /// File with queries into the dataset, in any format supported for the dataset
+    #[arg(short = 'Q', long)]
+    queryfile: PathBuf,
+
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Ground truth file generated by `ground-truth` for the queries
+    #[arg(short, long, required_unless_present = "datafile")]
+    groundtruth: Option<PathBuf>,
+
+    /// File with the dataset the index was built from, used to compute the ground truth
+    /// when no ground truth file is given
+    #[arg(short, long, conflicts_with = "groundtruth")]
+    datafile: Option<PathBuf>,
+
+    /// Recall@k to reach
+    #[arg(short, long, default_value_t = 0.9)]
+    recall: f64,
+
+    /// Number of nearest neighbors to find
+    #[arg(short, default_value_t = 10)]
+    k: usize,
+
+    /// Number of queries to tune on, taken from the start of the query file
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    sample: usize,
+
+    /// Largest beamwidth to consider
+    #[arg(short = 'E', long, default_value_t = 1024)]
+    max_ef: usize,
+
+    /// Location of the updated index file (default: overwrite the index file)
+    #[arg(short, long)]
+    outfile: Option<PathBuf>,
+}
+
+impl Action for Tune {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile)?.dispatch(self)
+    }
+}
+
+impl PointAction for Tune {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        // Changes appended while the tuned index is written over its file would be removed with the
+        // log
+        let _lock = self
+            .outfile
+            .is_none()
+            .then(|| lock_log(&self.indexfile))
+            .transpose()?;
+        let mut index_file = read_index(&self.indexfile, registry)?;
+        let start = index_file.attrs.start;
+
+        let queries = open_points::<P>(&self.queryfile, &self.dataset_name)?
+            .take(self.sample)
+            .collect::<Vec<_>>();
+
+        let truth = if let Some(groundtruth) = &self.groundtruth {
+            info!(path = ?groundtruth, "Opening");
+            let ids = read_rows(groundtruth, "knns")?;
+            let distances = read_distances(groundtruth)?;
+
+            // Ground truth ids are 1-indexed dataset rows, while the index uses keys relative to
+            // its first row. Neighbors outside the index can never be found.
+            ids.into_iter()
+                .zip(distances)
+                .take(queries.len())
+                .map(|(ids, distances)| Neighbors {
+                    ids: ids
+                        .into_iter()
+                        .map(|id| id.checked_sub(start + 1).unwrap_or(usize::MAX))
+                        .collect(),
+                    distances: distances.into_iter().map(P::key).collect(),
+                })
+                .collect::<Vec<_>>()
+        } else {
+            let datafile = self
+                .datafile
+                .as_ref()
+                .context("No ground truth or dataset")?;
+            let (dataset, _) = read_dataset::<P>(
+                datafile,
+                &self.dataset_name,
+                Some(start),
+                Some(index_file.index.size()),
+            )?;
+            let bruteforce = dataset.collect::<Bruteforce<_>>();
+
+            info!(k = self.k, "Computing ground truth");
+            exact_neighbors(&bruteforce, &queries, self.k)
+        };
+
+        if let Some(neighbors) = truth.iter().find(|n| n.ids.len() < self.k) {
+            bail!(
+                "k = {} is greater than the number of neighbors in the ground truth ({})",
+                self.k,
+                neighbors.ids.len()
+            );
+        }
+
+        info!(
+            recall = self.recall,
+            k = self.k,
+            max_ef = self.max_ef,
+            "Tuning"
+        );
+        let Some((ef, recall)) = tune_ef(
+            &index_file.index,
+            &queries,
+            &truth,
+            self.k,
+            self.recall,
+            self.max_ef,
+        ) else {
+            bail!(
+                "recall of {} is not reached with a beamwidth of up to {}",
+                self.recall,
+                self.max_ef
+            );
+        };
+        info!(ef, recall, "Tuned");
+
+        index_file.attrs.ef = Some(ef);
+        write_index(
+            self.outfile.as_ref().unwrap_or(&self.indexfile),
+            &mut index_file,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Add points to or remove points from an index file by appending the changes to its log
+#[derive(Args)]
+struct Update {
+    /// Index file to change
+    #[arg(short, long)]
+    indexfile: PathBuf,
+
+    /// File with points to add, in any format supported for the dataset. Added points get the
+    /// rows following the last row of the index.
+    #[arg(short, long, required_unless_present = "remove")]
+    add: Option<PathBuf>,
+
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("hamming"))]
+    dataset_name: String,
+
+    /// Ids of points to remove, as rows numbered from 1 as in result files
+    #[arg(short, long, num_args = 1..)]
+    remove: Vec<usize>,
+}
+
+impl Action for Update {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile)?.dispatch(self)
+    }
+}
+
+impl PointAction for Update {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        // Other updates wait until the changes are appended, such that they see them
+        let _lock = lock_log(&self.indexfile)?;
+        let mut index_file = read_index(&self.indexfile, registry)?;
+        let (start, dim, snapshot) = (
+            index_file.attrs.start,
+            index_file.attrs.dim,
+            index_file.attrs.snapshot,
+        );
+        let live = index_file.live();
+        let generation = live.generation();
+
+        // Changes are made to the index as well, such that invalid changes never reach the log
+        let mut entries = vec![];
+        if let Some(path) = &self.add {
+            let points = open_points::<P>(path, &self.dataset_name)?;
+            if let Some(dim) = dim.filter(|&dim| dim != points.dim()) {
+                bail!(
+                    "{path:?} holds points of {} elements, but the index holds points of {dim} elements",
+                    points.dim()
+                );
+            }
+            for point in points {
+                live.add(point.clone());
+                entries.push(LogEntry::Add(point));
+            }
+        }
+        for &id in &self.remove {
+            let key = id
+                .checked_sub(start + 1)
+                .with_context(|| format!("Id {id} is before the first row of the index"))?;
+            live.remove(Idx::from_usize(key))
+                .with_context(|| format!("Could not remove id {id}"))?;
+            entries.push(LogEntry::Remove(key));
+        }
+
+        info!(
+            changes = entries.len(),
+            generation = live.generation(),
+            size = live.size(),
+            "Updating"
+        );
+        append_log(&self.indexfile, snapshot, generation, &entries)
+    }
+}
+
+/// Write an index file with the changes in its log, such that the log can be removed
+///
+/// Added points are inserted into bruteforce, nsw and hnsw indexes, while removed points stay in the
+/// index and are skipped by searches, which still pass through them, until it is built again.
+#[derive(Args)]
+struct Compact {
+    /// Index file to compact
+    #[arg(short, long)]
+    indexfile: PathBuf,
+}
+
+impl Action for Compact {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile)?.dispatch(self)
+    }
+}
+
+impl PointAction for Compact {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        // Changes appended while the index is written would be removed with the log
+        let _lock = lock_log(&self.indexfile)?;
+        let mut index_file = read_index(&self.indexfile, registry)?;
+        // Graphs are extended with the parameters they were built with
+        let options = graph_options(&index_file.attrs.params);
+
+        if let Some(live) = index_file.index.as_live_mut() {
+            let added = live.added().len();
+            live.compact(|index, points| match &options {
+                Some(options) => index.insert(points, options),
+                None => Ok(points),
+            })?;
+
+            let left = live.added().len();
+            info!(
+                inserted = added - left,
+                removed = live.removed().len(),
+                "Compacted"
+            );
+            if left > 0 {
+                warn!(
+                    left,
+                    "Added points that can not be inserted into the index are still searched exhaustively"
+                );
+            }
+        }
+
+        write_index(&self.indexfile, &mut index_file)
+    }
+}
+
+/// Generate binary sketches from float vectors with random hyperplane LSH
+#[derive(Args)]
+struct CreateSketches {
+    /// File with float vectors (.h5, .hdf5, .fvecs or .npy)
+    #[arg(short, long)]
+    datafile: PathBuf,
+
+    /// Name of the dataset to read from HDF5 files
+    #[arg(long, default_value_t = String::from("emb"))]
+    dataset_name: String,
+
+    /// Location of resulting HDF5 file, sketches are written to its `hamming` dataset
+    #[arg(short, long, default_value_t = String::from("sketches.h5"))]
+    outfile: String,
+
+    /// File with the hyperplanes to sketch with. It is created if it does not exist, so a dataset
+    /// and its queries can be sketched with the same hyperplanes.
+    #[arg(short, long)]
+    model: Option<PathBuf>,
+
+    /// Seed of the random hyperplanes
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Learn a balanced, uncorrelated selection of hyperplanes from a sample of the vectors
+    #[arg(short, long, default_value_t = false)]
+    balanced: bool,
+
+    /// Number of vectors to learn from, taken from the start of the datafile
+    #[arg(long, default_value_t = 10_000)]
+    sample: usize,
+
+    /// Number of random hyperplanes to select from when learning
+    #[arg(long, default_value_t = 4096)]
+    candidates: usize,
+}
+
+impl CreateSketches {
+    fn sketcher(&self, dataset: &mut dyn DatasetReader<Array1<f32>>) -> Result<Sketcher> {
+        if let Some(path) = self.model.as_ref().filter(|path| path.exists()) {
+            info!(?path, "Reading hyperplanes");
+            let reader = BufReader::new(File::open(path)?);
+            return deserialize_from(reader).context("Could not read hyperplanes");
+        }
+
+        let sketcher = if self.balanced {
+            let sample = dataset
+                .take(self.sample)
+                .map(|v| v.to_vec())
+                .collect::<Vec<_>>();
+            dataset.seek(0)?;
+
+            if sample.is_empty() {
+                bail!("no vectors to learn from");
+            }
+            if self.candidates < Sketch::WORDS * 64 {
+                bail!("at least {} candidates are needed", Sketch::WORDS * 64);
+            }
+
+            info!(
+                sample = sample.len(),
+                candidates = self.candidates,
+                seed = self.seed,
+                "Learning hyperplanes"
+            );
+            Sketcher::learned(&sample, self.candidates, self.seed)
+        } else {
+            Sketcher::random(dataset.dim(), self.seed)
+        };
+
+        if let Some(path) = &self.model {
+            info!(?path, "Writing hyperplanes");
+            serialize_into(BufWriter::new(File::create(path)?), &sketcher)?;
+        }
+
+        Ok(sketcher)
+    }
+}
+
+impl Action for CreateSketches {
+    fn act(self) -> Result<()> {
+        const CHUNK_SIZE: usize = 50_000;
+
+        info!(path = ?self.datafile, name = self.dataset_name, "Opening");
+        let mut dataset = open_dataset::<Array1<f32>, f32>(&self.datafile, &self.dataset_name, 0)?;
+        let sketcher = self.sketcher(dataset.as_mut())?;
+
+        if sketcher.dim() != dataset.dim() {
+            bail!(
+                "hyperplanes have dimension {} but vectors have dimension {}",
+                sketcher.dim(),
+                dataset.dim()
+            );
+        }
+
+        let size = dataset.size();
+        info!(outfile = self.outfile, size, "Sketching");
+        let sketches = BufferedDataset::<'_, Array1<u64>, u64>::create(
+            &self.outfile,
+            (size, Sketch::WORDS),
+            "hamming",
+        )?;
+
+        let mut row = 0;
+        loop {
+            let chunk = dataset.by_ref().take(CHUNK_SIZE).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                break;
+            }
+
+            let chunk = chunk
+                .par_iter()
+                .map(|v| sketcher.sketch(v.as_slice().expect("rows are contiguous")))
+                .collect::<Vec<_>>();
+
+            for sketch in chunk {
+                sketches.write_row(sketch.into(), row)?;
+                row += 1;
+            }
+            debug!(row, "{}%", row * 100 / size);
+        }
+
+        Ok(())
+    }
+}
+
+// Query given as its elements, e.g. by a request or a line of input, into an index of points of
+// `dim` elements
+fn query_point<P: DataPoint>(query: Vec<P::Element>, dim: Option<usize>) -> Result<P> {
+    if query.is_empty() {
+        bail!("query is empty");
+    }
+    if let Some(dim) = P::RAW_DIM.or(dim) {
+        if query.len() != dim {
+            bail!(hnsw_itu::Error::Dimension {
+                expected: dim,
+                actual: query.len(),
+            });
+        }
+    }
+
+    Ok(P::from(Array1::from(query)))
+}
+
+#[derive(Serialize)]
+struct SearchResponse<D> {
+    // Dataset rows of the neighbors, numbered from 1 as in result files
+    ids: Vec<u64>,
+    distances: Vec<D>,
+}
+
+impl<D> SearchResponse<D> {
+    // Sorted neighbors of a query into an index starting at dataset row `start`
+    fn new<P: DataPoint<Dist = D>>(mut res: Vec<Distance<'_, P>>, start: usize) -> Self {
+        res.sort();
+        let (ids, distances) = res
+            .iter()
+            .map(|d| ((d.key.into_usize() + start) as u64 + 1, P::dist(d.distance)))
+            .unzip();
+
+        Self { ids, distances }
+    }
+}
+
+/// Query an index file with queries read from stdin, one per line, and write the nearest
+/// neighbors of each as a line of JSON to stdout
+#[derive(Args, Debug)]
+struct QueryStream {
+    /// Index file to query. Multiple files are queried together as shards, each covering the
+    /// dataset rows it was indexed from.
+    #[arg(short, long, num_args = 1.., required = true)]
+    indexfile: Vec<PathBuf>,
+
+    /// Number of nearest neighbors to find
+    #[arg(short, default_value_t = 10)]
+    k: usize,
+
+    /// Beamwidth during search, or number of lists to probe for IVF [default: the beamwidth
+    /// chosen by `tune`, otherwise 96]
+    #[arg(short = 'e')]
+    ef: Option<usize>,
+
+    /// Encoding of queries that are not JSON arrays and have no `hex:` or `b64:` prefix (hex or
+    /// base64)
+    #[arg(long)]
+    encoding: Option<RowEncoding>,
+}
+
+impl Action for QueryStream {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile[0])?.dispatch(self)
+    }
+}
+
+impl PointAction for QueryStream {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        let index_file = read_sharded_index(&self.indexfile, registry)?;
+        let ef = self.ef.or(index_file.attrs.ef).unwrap_or(96);
+        info!(k = self.k, ef, "Reading queries from stdin");
+
+        let mut stdout = io::stdout().lock();
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // Queries are JSON arrays of elements, or rows as in raw files in hex or base64
+            let query = if line.starts_with('[') {
+                serde_json::from_str(line).map_err(anyhow::Error::from)
+            } else {
+                decode_row(line, self.encoding)
+            };
+
+            // Every query gets a line, such that output lines match input lines. Queries are
+            // searched as by the `/search` endpoint of `serve`.
+            let dim = index_file.attrs.dim;
+            let res = query.and_then(|query| {
+                let query = query_point::<P>(query, dim)?;
+                let res = index_file.try_search(&query, self.k, ef)?;
+                Ok(SearchResponse::new(res, index_file.attrs.start))
+            });
+            let output = match res {
+                Ok(res) => serde_json::to_string(&res)?,
+                Err(e) => {
+                    warn!(%e, "Could not answer query");
+                    json!({ "error": format!("{e:#}") }).to_string()
+                }
+            };
+
+            writeln!(stdout, "{output}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serve an index file over HTTP, answering queries with JSON
+#[derive(Args, Debug)]
+struct Serve {
+    /// Index file to serve. Multiple files are served together as shards, each covering the
+    /// dataset rows it was indexed from.
+    #[arg(short, long, num_args = 1.., required = true)]
+    indexfile: Vec<PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long, default_value_t = String::from("127.0.0.1:8080"))]
+    address: String,
+
+    /// Number of threads handling requests [default: number of CPUs]
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+
+    /// Number of nearest neighbors to find when a request does not give `k`
+    #[arg(short, default_value_t = 10)]
+    k: usize,
+
+    /// Beamwidth when a request does not give `ef` [default: the beamwidth chosen by `tune`,
+    /// otherwise 96]
+    #[arg(short = 'e')]
+    ef: Option<usize>,
+
+    /// Seconds between checks for changes to the index files, at least 1. Modified files are
+    /// reloaded, as they are on SIGHUP.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    reload_interval: u64,
+
+    /// Seconds to wait for a client to send its request or receive the response before the
+    /// connection is dropped, 0 to wait indefinitely
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+}
+
+impl Action for Serve {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile[0])?.dispatch(self)
+    }
+}
+
+impl PointAction for Serve {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        let index_file = read_sharded_index(&self.indexfile, registry)?;
+        let server = Arc::new(Server {
+            index: IndexHandle::new(Arc::new(index_file)),
+            k: self.k,
+            ef: self.ef,
+            queries: AtomicUsize::new(0),
+            reloads: AtomicUsize::new(0),
+            timeout: Duration::from_secs(self.timeout),
+        });
+
+        reload_on_sighup();
+        let watcher = Arc::clone(&server);
+        let interval = Duration::from_secs(self.reload_interval);
+        let registry = registry.clone();
+        thread::spawn(move || watcher.watch(&self.indexfile, interval, &registry));
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            // A panicking request must not take the server down with it
+            .panic_handler(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                error!(message, "Request panicked");
+            })
+            .build()?;
+        let listener = TcpListener::bind(&self.address)?;
+        info!(
+            address = %listener.local_addr()?,
+            threads = pool.current_num_threads(),
+            "Serving"
+        );
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(%e, "Could not accept connection");
+                    continue;
+                }
+            };
+
+            let server = Arc::clone(&server);
+            pool.spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    warn!(%e, "Could not handle request");
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// Set by the SIGHUP handler and cleared when the index is reloaded
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn reload_on_sighup() {
+    extern "C" fn handle(_: libc::c_int) {
+        RELOAD.store(true, Ordering::Relaxed);
+    }
+
+    // The handler only stores to an atomic, which is safe to do in a signal handler
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn reload_on_sighup() {}
+
+struct Server<P> {
+    // Searches in flight finish on the index they started on when it is reloaded
+    index: IndexHandle<P, IndexFile<P>>,
+    // Defaults for requests without `k` or `ef`
+    k: usize,
+    ef: Option<usize>,
+    // Number of queries answered, including those of batches
+    queries: AtomicUsize,
+    reloads: AtomicUsize,
+    // Slow clients would otherwise hold a thread of the pool indefinitely
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest<E> {
+    query: Vec<E>,
+    k: Option<usize>,
+    ef: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct BatchSearchRequest<E> {
+    queries: Vec<Vec<E>>,
+    k: Option<usize>,
+    ef: Option<usize>,
+}
+
+impl<P: DataPoint> Server<P> {
+    // Reload the index files when they have been modified, or on SIGHUP. Files are only read once
+    // they have not changed for an `interval`, such that files being written are not read.
+    fn watch(&self, paths: &[PathBuf], interval: Duration, registry: &Registry<P>) {
+        // Logs of changes are read with their index files
+        let modified = || {
+            paths
+                .iter()
+                .flat_map(|path| [path.clone(), log_path(path)])
+                .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+                .collect::<Vec<_>>()
+        };
+        let mut loaded = modified();
+        let mut seen = loaded.clone();
+
+        loop {
+            thread::sleep(interval);
+
+            let current = modified();
+            if current != seen {
+                seen = current;
+                continue;
+            }
+            if current == loaded && !RELOAD.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            match read_sharded_index(paths, registry) {
+                Ok(index_file) => {
+                    let size = index_file.index.size();
+                    self.index.swap(Arc::new(index_file));
+                    self.reloads.fetch_add(1, Ordering::Relaxed);
+                    loaded = current;
+                    info!(size, "Reloaded index");
+                }
+                // Modified files are retried once they change again
+                Err(e) => {
+                    loaded = current;
+                    warn!(%e, "Could not reload index, serving the previous one");
+                }
+            }
+        }
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<()> {
+        // A zero timeout is rejected by the socket, it means no timeout here
+        let timeout = Some(self.timeout).filter(|timeout| !timeout.is_zero());
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        let request = http::read_request(&mut BufReader::new(&stream));
+        let (status, body) = match request {
+            Ok(request) => {
+                debug!(method = request.method, path = request.path, "Request");
+                self.respond(&request)
+            }
+            Err(e) => (400, json!({ "error": e.to_string() })),
+        };
+
+        http::write_response(&mut BufWriter::new(&stream), status, &body.to_string())?;
+
+        Ok(())
+    }
+
+    fn respond(&self, request: &http::Request) -> (u16, Value) {
+        let result = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/health") => Ok(json!({ "status": "ok" })),
+            ("GET", "/stats") => Ok(self.stats()),
+            ("POST", "/search") => self.search(&request.body),
+            ("POST", "/batch_search") => self.batch_search(&request.body),
+            (_, "/health" | "/stats" | "/search" | "/batch_search") => {
+                return (405, json!({ "error": "method not allowed" }))
+            }
+            _ => return (404, json!({ "error": "not found" })),
+        };
+
+        match result {
+            Ok(body) => (200, body),
+            Err(e) => (400, json!({ "error": format!("{e:#}") })),
+        }
+    }
+
+    fn stats(&self) -> Value {
+        let index_file = self.index.load();
+        let attrs = &index_file.attrs;
+        json!({
+            "point_type": format!("{:?}", P::TYPE),
+            "size": index_file.size(),
+            "start": attrs.start,
+            "algo": format!("{:?}", attrs.algo),
+            "params": attrs.params,
+            "k": self.k,
+            "ef": self.ef(&index_file),
+            "queries": self.queries.load(Ordering::Relaxed),
+            "reloads": self.reloads.load(Ordering::Relaxed),
+        })
+    }
+
+    fn ef(&self, index_file: &IndexFile<P>) -> usize {
+        self.ef.or(index_file.attrs.ef).unwrap_or(96)
+    }
+
+    fn params(
+        &self,
+        index_file: &IndexFile<P>,
+        k: Option<usize>,
+        ef: Option<usize>,
+    ) -> Result<(usize, usize)> {
+        let (k, ef) = (k.unwrap_or(self.k), ef.unwrap_or(self.ef(index_file)));
+        if k == 0 {
+            bail!(hnsw_itu::Error::InvalidParameter {
+                name: "k",
+                reason: "must be positive",
+            });
+        }
+        if ef == 0 {
+            bail!(hnsw_itu::Error::InvalidParameter {
+                name: "ef",
+                reason: "must be positive",
+            });
+        }
+
+        Ok((k, ef))
+    }
+
+    fn search(&self, body: &[u8]) -> Result<Value> {
+        let request: SearchRequest<P::Element> = serde_json::from_slice(body)?;
+        let index_file = self.index.load();
+        let (k, ef) = self.params(&index_file, request.k, request.ef)?;
+        let query = query_point(request.query, index_file.attrs.dim)?;
+
+        let res = index_file.try_search(&query, k, ef)?;
+        self.queries.fetch_add(1, Ordering::Relaxed);
+
+        Ok(serde_json::to_value(SearchResponse::new(
+            res,
+            index_file.attrs.start,
+        ))?)
+    }
+
+    fn batch_search(&self, body: &[u8]) -> Result<Value> {
+        let request: BatchSearchRequest<P::Element> = serde_json::from_slice(body)?;
+        let index_file = self.index.load();
+        let (k, ef) = self.params(&index_file, request.k, request.ef)?;
+        let queries = request
+            .queries
+            .into_iter()
+            .map(|query| query_point(query, index_file.attrs.dim))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Queries are searched on the threads of the pool handling requests
+        let results = queries
+            .par_iter()
+            .map(|query| index_file.try_search(query, k, ef))
+            .collect::<hnsw_itu::Result<Vec<_>>>()?;
+        self.queries.fetch_add(results.len(), Ordering::Relaxed);
+
+        let results = results
+            .into_iter()
+            .map(|res| SearchResponse::new(res, index_file.attrs.start))
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "results": results }))
+    }
+}
+
 /// Read information from index
 #[derive(Args)]
 struct Inspect {
@@ -762,66 +3440,123 @@ struct Inspect {
 
 impl Action for Inspect {
     fn act(self) -> Result<()> {
-        let index_file = read_index(&self.indexfile)?;
+        read_point_type(&self.indexfile)?.dispatch(self)
+    }
+}
+
+impl PointAction for Inspect {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        let index_file = read_index(&self.indexfile, registry)?;
 
+        println!("{:?} points", P::TYPE);
         println!("{:?}", index_file.attrs);
+        println!(
+            "rows {}..{}",
+            index_file.attrs.start,
+            index_file.attrs.start + index_file.index.size()
+        );
 
-        fn print_layer<T>(name: String, layer: &SimpleGraph<T>) {
-            let node_count = layer.nodes().len();
-            let mut connections = layer
-                .adj_lists()
-                .into_iter()
-                .map(|l| l.len())
-                .collect::<Vec<_>>();
-            connections.sort();
-            let total_connections: usize = connections.iter().sum();
-            let avg_connections = total_connections / node_count;
-            println!("\n{name} has {node_count} nodes, {total_connections} total connections, and {avg_connections} average connections");
-
-            let len = connections.len();
-            println!("connection distribution:");
-            for i in 0..11 {
-                println!("p{} {}", i * 10, connections[(len - 1).min(len / 10 * i)]);
-            }
+        index_file.index.inspect();
+
+        Ok(())
+    }
+}
+
+/// Check the graphs of an index file for broken invariants and report those found
+#[derive(Args)]
+struct Verify {
+    /// Index file to verify
+    #[arg(short, long)]
+    indexfile: PathBuf,
+
+    /// Max number of edges for each node, by default the one the index was built with
+    #[arg(short = 'M', long)]
+    max_connections: Option<usize>,
+
+    /// Number of violations to print
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    /// Connect nodes that are not reachable from the entry point, with the parameters the index
+    /// was built with, and write the repaired index file before verifying it
+    #[arg(long, default_value_t = false)]
+    repair: bool,
+}
+
+impl Action for Verify {
+    fn act(self) -> Result<()> {
+        read_point_type(&self.indexfile)?.dispatch(self)
+    }
+}
+
+impl PointAction for Verify {
+    fn run<P: DataPoint>(self, registry: &Registry<P>) -> Result<()> {
+        // Changes appended while a repaired index is written would be removed with the log
+        let _lock = self.repair.then(|| lock_log(&self.indexfile)).transpose()?;
+        let mut index_file = read_index(&self.indexfile, registry)?;
+        if !matches!(index_file.attrs.algo, Algorithm::Nsw | Algorithm::Hnsw) {
+            bail!(
+                "{:?} indexes have no graph to verify",
+                index_file.attrs.algo
+            );
         }
 
-        match index_file.index {
-            Indexes::Bruteforce(_) => (),
-            Indexes::NSW(nsw) => {
-                let graph = nsw.graph();
-                print_layer("base".to_string(), graph);
+        if self.repair {
+            let options = graph_options(&index_file.attrs.params)
+                .context("Parameters of the index are unknown")?;
+            let added = index_file.index.repair(&options)?;
+            info!(added, "Connected unreachable nodes");
+            write_index(&self.indexfile, &mut index_file)?;
+        }
 
-                let size = graph.size();
-                let res = nsw.search(graph.get(0).unwrap(), size, size);
-                println!(
-                    "\nquery on whole index returned {}/{} elements",
-                    res.len(),
-                    size
-                );
-            }
-            Indexes::HNSW(hnsw) => {
-                for (i, layer) in hnsw.layers().into_iter().enumerate().rev() {
-                    print_layer(format!("layer{i}"), layer);
-                }
-                let base = hnsw.base();
-                print_layer("base".to_string(), base);
-
-                let size = hnsw.size();
-                let res = hnsw.search(base.get(0).unwrap(), size, size);
-                println!(
-                    "\nquery on whole index returned {}/{} elements",
-                    res.len(),
-                    size
-                );
+        let max_connections = self
+            .max_connections
+            .or_else(|| param(&index_file.attrs.params, "M"));
+        if max_connections.is_none() {
+            warn!("Not checking degrees, as the max number of edges is unknown");
+        }
+
+        let sharded = index_file.index.tag() == "sharded";
+        let violations = index_file.index.violations(max_connections);
+        for (start, violation) in violations.iter().take(self.limit) {
+            match sharded {
+                true => println!("shard at row {start}: {violation}"),
+                false => println!("{violation}"),
             }
         }
 
+        if !violations.is_empty() {
+            bail!("{} violations found", violations.len());
+        }
+
+        println!("No violations found in {} points", index_file.index.size());
         Ok(())
     }
 }
 
+// Options of a graph index as it was built, from its `params` attribute
+fn graph_options(params: &str) -> Option<NSWOptions> {
+    Some(NSWOptions {
+        ef_construction: param(params, "efc")?,
+        connections: param(params, "m")?,
+        max_connections: param(params, "M")?,
+        ..Default::default()
+    })
+}
+
+// Value of a parameter in the `params` attribute, e.g. `M` in `index=(efc=96,m=24,M=256)`
+fn param(params: &str, name: &str) -> Option<usize> {
+    params
+        .split(['(', ')', ','])
+        .filter_map(|param| param.split_once('='))
+        .find(|&(key, _)| key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use super::*;
 
     #[test]
@@ -829,4 +3564,242 @@ mod tests {
         use clap::CommandFactory;
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn index_file_point_type() {
+        let path = std::env::temp_dir().join(format!("hnsw-itu-{}.idx", std::process::id()));
+        let points = [vec![0.0, 1.0], vec![1.0, 0.0]];
+        let mut index_file = IndexFile {
+            attrs: ResultAttrs::default(),
+            index: Box::new(
+                points
+                    .into_iter()
+                    .map(Vector::<Cosine>::new)
+                    .collect::<Bruteforce<_>>(),
+            ),
+        };
+        write_index(&path, &mut index_file).unwrap();
+
+        assert_eq!(read_point_type(&path).unwrap(), PointType::Cosine);
+        assert_eq!(
+            read_index::<Vector<Cosine>>(&path, &Registry::builtin())
+                .unwrap()
+                .index
+                .size(),
+            2
+        );
+        assert!(read_index::<Vector<Euclidean>>(&path, &Registry::builtin()).is_err());
+        assert!(read_index::<Sketch>(&path, &Registry::builtin()).is_err());
+
+        // Kinds are only read once they are registered
+        let mut registry = Registry::new();
+        assert!(read_index::<Vector<Cosine>>(&path, &registry).is_err());
+        registry.register("bruteforce", |reader, _| {
+            read_serialized::<_, Bruteforce<Vector<Cosine>>>(reader)
+        });
+        assert!(read_index(&path, &registry).is_ok());
+
+        // Files of other formats are rejected before their point type is read
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len()] += 1;
+        fs::write(&path, &bytes).unwrap();
+        let e = read_point_type(&path).unwrap_err();
+        let version = format!("format version {}", FORMAT_VERSION + 1);
+        assert!(e.to_string().contains(&version));
+        fs::write(&path, &bytes[MAGIC.len()..]).unwrap();
+        assert!(read_index::<Vector<Cosine>>(&path, &Registry::builtin()).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn query_dimension() {
+        type P = Vector<Euclidean>;
+        assert_eq!(query_point::<P>(vec![1.0, 2.0], Some(2)).unwrap().dim(), 2);
+        assert!(query_point::<P>(vec![1.0, 2.0, 3.0], Some(2)).is_err());
+        assert!(query_point::<P>(vec![], None).is_err());
+        assert!(query_point::<Sketch>(vec![1; Sketch::WORDS - 1], None).is_err());
+    }
+
+    #[test]
+    fn index_checkpoint() {
+        type P = Vector<Euclidean>;
+        let path = std::env::temp_dir().join(format!("hnsw-itu-{}.ckpt", std::process::id()));
+        let points = || (0..100).map(|i| P::new(vec![i as f32, (i % 7) as f32]));
+        let options = AlgorithmOptions {
+            ef_construction: 16,
+            connections: 4,
+            max_connections: 8,
+            single_threaded: true,
+            size: Some(100),
+            checkpoint_every: Some(30),
+            ..Default::default()
+        };
+        let mut full = Algorithm::Hnsw.builder::<P>(&options).unwrap().unwrap();
+        full.try_extend(points(), true).unwrap();
+
+        // Stop the build at its second checkpoint and resume it from there
+        let mut builder = Algorithm::Hnsw.builder::<P>(&options).unwrap().unwrap();
+        let mut checkpoints = 0;
+        let stopped = extend_graph(&mut builder, points(), &options, |builder| {
+            let attrs = ResultAttrs::default();
+            write_checkpoint(&path, &Checkpoint { attrs, builder })?;
+            checkpoints += 1;
+            match checkpoints {
+                2 => bail!("stopped"),
+                _ => Ok(()),
+            }
+        });
+        assert!(stopped.is_err());
+
+        let mut resumed = read_checkpoint::<P>(&path).unwrap().builder;
+        assert_eq!(resumed.size(), 60);
+        resumed.try_extend(points().skip(60), true).unwrap();
+
+        // Neighbors are kept in hash sets, so only the edges can be compared
+        let edges = |builder: Builders<P>| {
+            let Builders::Hnsw(builder) = builder else {
+                panic!("not an HNSW builder");
+            };
+            let hnsw = builder.build();
+            let layers = hnsw.layers().iter().map(|l| l.adj_lists());
+            layers
+                .chain([hnsw.base().adj_lists()])
+                .map(|adj_lists| {
+                    adj_lists
+                        .iter()
+                        .map(|adj_list| adj_list.iter().copied().collect::<BTreeSet<_>>())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(edges(resumed), edges(full));
+        assert!(read_checkpoint::<Sketch>(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn index_file_log() {
+        type P = Vector<Euclidean>;
+        let path = std::env::temp_dir().join(format!("hnsw-itu-log-{}.idx", std::process::id()));
+        let point = |x| P::new(vec![x, 0.0]);
+        let new_index_file = || IndexFile {
+            attrs: ResultAttrs::default(),
+            index: Box::new(
+                [0.0, 1.0, 2.0]
+                    .map(point)
+                    .into_iter()
+                    .collect::<Bruteforce<_>>(),
+            ),
+        };
+        let mut index_file = new_index_file();
+        write_index(&path, &mut index_file).unwrap();
+        let snapshot = index_file.attrs.snapshot;
+
+        let ids = |index_file: &IndexFile<P>| {
+            let res = index_file.try_search(&point(2.9), 3, 3).unwrap();
+            SearchResponse::new(res, index_file.attrs.start).ids
+        };
+
+        append_log(&path, snapshot, 0, &[LogEntry::Add(point(3.0))]).unwrap();
+        append_log::<P>(&path, snapshot, 1, &[LogEntry::Remove(2)]).unwrap();
+        let index_file = read_index::<P>(&path, &Registry::builtin()).unwrap();
+        assert_eq!(index_file.size(), 3);
+        assert_eq!(ids(&index_file), vec![4, 2, 1]);
+
+        // An entry cut off by a crash is ignored, and overwritten by the next one
+        let mut log = fs::OpenOptions::new()
+            .append(true)
+            .open(log_path(&path))
+            .unwrap();
+        log.write_all(&[16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2])
+            .unwrap();
+        assert_eq!(
+            read_index::<P>(&path, &Registry::builtin()).unwrap().size(),
+            3
+        );
+        append_log::<P>(&path, snapshot, 2, &[LogEntry::Remove(0)]).unwrap();
+        assert_eq!(
+            ids(&read_index::<P>(&path, &Registry::builtin()).unwrap()),
+            vec![4, 2]
+        );
+
+        // An entry that does not match its checksum is corrupt rather than cut off, and neither
+        // it nor the entries before it are dropped
+        let mut bytes = fs::read(log_path(&path)).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(log_path(&path), &bytes).unwrap();
+        assert!(read_index::<P>(&path, &Registry::builtin()).is_err());
+        assert!(append_log::<P>(&path, snapshot, 3, &[LogEntry::Remove(1)]).is_err());
+        bytes[last] ^= 1;
+        fs::write(log_path(&path), &bytes).unwrap();
+        assert_eq!(
+            ids(&read_index::<P>(&path, &Registry::builtin()).unwrap()),
+            vec![4, 2]
+        );
+
+        // A snapshot holds the changes, and a log left from before it is skipped
+        let mut index_file = read_index::<P>(&path, &Registry::builtin()).unwrap();
+        let log = fs::read(log_path(&path)).unwrap();
+        write_index(&path, &mut index_file).unwrap();
+        assert!(!log_path(&path).exists());
+        fs::write(log_path(&path), &log).unwrap();
+        assert_eq!(
+            ids(&read_index::<P>(&path, &Registry::builtin()).unwrap()),
+            vec![4, 2]
+        );
+
+        // Also by an index that was built again, which has no changes of its own
+        write_index(&path, &mut new_index_file()).unwrap();
+        fs::write(log_path(&path), &log).unwrap();
+        assert_eq!(
+            ids(&read_index::<P>(&path, &Registry::builtin()).unwrap()),
+            vec![3, 2, 1]
+        );
+
+        // Appending to a log of another snapshot starts it over
+        let snapshot = read_index::<P>(&path, &Registry::builtin())
+            .unwrap()
+            .attrs
+            .snapshot;
+        append_log::<P>(&path, snapshot, 0, &[LogEntry::Remove(1)]).unwrap();
+        assert_eq!(
+            ids(&read_index::<P>(&path, &Registry::builtin()).unwrap()),
+            vec![3, 1]
+        );
+
+        fs::remove_file(log_path(&path)).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sweep_pareto_frontier() {
+        let run = |ef, recall, qps| SweepRun {
+            k: 10,
+            ef,
+            recall,
+            min_recall: 0.0,
+            qps,
+            mean_latency: 0.0,
+            p50_latency: 0.0,
+            p95_latency: 0.0,
+            p99_latency: 0.0,
+        };
+
+        let runs = vec![
+            run(16, 0.5, 1000.0),
+            run(32, 0.7, 800.0),
+            run(48, 0.6, 700.0),
+            run(64, 0.9, 500.0),
+            run(96, 0.9, 400.0),
+        ];
+
+        let frontier = pareto_frontier(runs);
+        assert_eq!(
+            frontier.iter().map(|r| r.ef).collect::<Vec<_>>(),
+            vec![64, 32, 16]
+        );
+    }
 }
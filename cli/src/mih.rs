@@ -0,0 +1,222 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use anyhow::{ensure, Result};
+use hnsw_itu::{Distance, Idx, IdxExt, Index, IndexBuilder, Point};
+use serde::{Deserialize, Serialize};
+
+use crate::Sketch;
+
+const BITS: usize = 1024;
+
+// Multi-index hashing (Norouzi et al.): exact k-NN in Hamming space by splitting the sketches into
+// `m` substrings, each indexed by its own hash table. A point within distance `r` of the query
+// must match the query within distance `r / m` on at least one substring.
+#[derive(Serialize, Deserialize)]
+pub struct MIH {
+    points: Vec<Sketch>,
+    width: usize,
+    tables: Vec<HashMap<u64, Vec<Idx>>>,
+}
+
+impl MIH {
+    pub fn new(substrings: usize) -> Result<Self> {
+        ensure!(
+            (BITS / 64..=BITS).contains(&substrings) && BITS.is_multiple_of(substrings),
+            "substrings must divide {BITS} into substrings of at most 64 bits, not {substrings}"
+        );
+
+        Ok(Self {
+            points: vec![],
+            width: BITS / substrings,
+            tables: vec![HashMap::new(); substrings],
+        })
+    }
+
+    pub fn substrings(&self) -> usize {
+        self.tables.len()
+    }
+
+    fn substring(&self, sketch: &Sketch, i: usize) -> u64 {
+        let offset = i * self.width;
+        let mask = u64::MAX >> (64 - self.width);
+        (sketch.data[offset / 64] >> (offset % 64)) & mask
+    }
+
+    // Keys of all points whose `i`th substring is exactly at distance `r` from `q`
+    fn bucket_keys(&self, i: usize, q: u64, r: usize) -> Vec<Idx> {
+        let table = &self.tables[i];
+
+        if binomial(self.width, r) <= table.len() as u128 {
+            Combinations::new(self.width, r)
+                .filter_map(|mask| table.get(&(q ^ mask)))
+                .flatten()
+                .copied()
+                .collect()
+        } else {
+            table
+                .iter()
+                .filter(|(&s, _)| (s ^ q).count_ones() as usize == r)
+                .flat_map(|(_, keys)| keys)
+                .copied()
+                .collect()
+        }
+    }
+}
+
+impl IndexBuilder<Sketch> for MIH {
+    type Index = Self;
+
+    fn add(&mut self, point: Sketch) {
+        let key = Idx::from_usize(self.points.len());
+        for i in 0..self.substrings() {
+            let s = self.substring(&point, i);
+            self.tables[i].entry(s).or_default().push(key);
+        }
+        self.points.push(point);
+    }
+
+    fn build(self) -> Self::Index {
+        self
+    }
+}
+
+impl Extend<Sketch> for MIH {
+    fn extend<T: IntoIterator<Item = Sketch>>(&mut self, iter: T) {
+        for i in iter {
+            self.add(i);
+        }
+    }
+}
+
+impl Index<Sketch> for MIH {
+    fn size(&self) -> usize {
+        self.points.len()
+    }
+
+    // Exact search, `ef` is unused
+    fn search<'a>(&'a self, query: &Sketch, k: usize, _ef: usize) -> Vec<Distance<'a, Sketch>> {
+        let k = k.min(self.size());
+        if k == 0 {
+            return vec![];
+        }
+
+        let m = self.substrings();
+        let qs = (0..m).map(|i| self.substring(query, i)).collect::<Vec<_>>();
+        let mut visited = HashSet::new();
+        let mut heap = BinaryHeap::<Distance<'a, Sketch>>::with_capacity(k + 1);
+
+        for r in 0..=self.width {
+            for (i, &q) in qs.iter().enumerate() {
+                for key in self.bucket_keys(i, q, r) {
+                    if !visited.insert(key) {
+                        continue;
+                    }
+
+                    let point = &self.points[key.into_usize()];
+                    heap.push(Distance::new(point.distance(query), key, point));
+                    if heap.len() > k {
+                        heap.pop();
+                    }
+                }
+            }
+
+            // Every point not yet visited is at least `m * (r + 1)` away
+            let done = heap.len() == k && heap.peek().unwrap().distance < m * (r + 1);
+            if done || visited.len() == self.size() {
+                break;
+            }
+        }
+
+        heap.into_sorted_vec()
+    }
+}
+
+fn binomial(n: usize, r: usize) -> u128 {
+    (0..r as u128).fold(1, |acc, i| acc * (n as u128 - i) / (i + 1))
+}
+
+// All `width`-bit masks with exactly `r` bits set, in increasing order (Gosper's hack)
+struct Combinations {
+    next: Option<u128>,
+    limit: u128,
+}
+
+impl Combinations {
+    fn new(width: usize, r: usize) -> Self {
+        Self {
+            next: (r <= width).then(|| (1u128 << r) - 1),
+            limit: 1 << width,
+        }
+    }
+}
+
+impl Iterator for Combinations {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cur = self.next?;
+
+        self.next = if cur == 0 {
+            None
+        } else {
+            let c = cur & cur.wrapping_neg();
+            let r = cur + c;
+            let next = (((r ^ cur) >> 2) / c) | r;
+            (next < self.limit).then_some(next)
+        };
+
+        Some(cur as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hnsw_itu::Bruteforce;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn combinations() {
+        assert_eq!(Combinations::new(4, 0).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(
+            Combinations::new(4, 2).collect::<Vec<_>>(),
+            vec![0b0011, 0b0101, 0b0110, 0b1001, 0b1010, 0b1100]
+        );
+        assert_eq!(Combinations::new(64, 1).count(), 64);
+        assert_eq!(Combinations::new(64, 2).count() as u128, binomial(64, 2));
+    }
+
+    #[test]
+    fn mih_is_exact() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let center = Sketch::new(rng.gen());
+        // Points clustered around `center` so the search has to expand the radius a few times
+        let points = (0..500)
+            .map(|_| {
+                let mut data = center.data;
+                for _ in 0..rng.gen_range(0..64) {
+                    let bit = rng.gen_range(0..1024);
+                    data[bit / 64] ^= 1 << (bit % 64);
+                }
+                Sketch::new(data)
+            })
+            .collect::<Vec<_>>();
+
+        assert!(MIH::new(7).is_err());
+        let mut mih = MIH::new(32).unwrap();
+        mih.extend(points.clone());
+        let bruteforce = points.into_iter().collect::<Bruteforce<_>>();
+
+        for k in [1, 10, 100] {
+            let mut expected = bruteforce.search(&center, k, k);
+            expected.sort();
+            let actual = mih.search(&center, k, k);
+
+            assert_eq!(
+                actual.iter().map(|d| d.distance).collect::<Vec<_>>(),
+                expected.iter().map(|d| d.distance).collect::<Vec<_>>()
+            );
+        }
+    }
+}
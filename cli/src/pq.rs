@@ -0,0 +1,402 @@
+use std::{fmt, marker::PhantomData};
+
+use hnsw_itu::Point;
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+use rayon::prelude::*;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+#[cfg(feature = "instrument")]
+use tracing::trace;
+
+use crate::{distance_key, Euclidean, Metric};
+
+// Maximum number of centroids in every codebook, such that codes fit in a u8
+const CENTROIDS: usize = 256;
+
+// Product quantization (Jégou et al.): vectors are split into subvectors of equal dimension, and
+// every subvector is encoded as the nearest centroid in the codebook of its subspace. Distances are
+// squared euclidean and approximated by the distances between centroids.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PQ {
+    dim: usize,
+    subspaces: usize,
+    centroids: usize,
+    // `centroids` centroids of `dim / subspaces` elements for every subspace
+    codebooks: Vec<f32>,
+    // Distances between every pair of centroids for every subspace, for symmetric distance
+    table: Vec<f32>,
+}
+
+impl PQ {
+    // Learn the codebooks with `iterations` of k-means on a sample of the vectors
+    pub fn train(sample: &[Vec<f32>], subspaces: usize, iterations: usize, seed: u64) -> Self {
+        assert!(!sample.is_empty(), "sample must not be empty");
+        let dim = sample[0].len();
+        assert!(
+            subspaces > 0 && dim.is_multiple_of(subspaces),
+            "dimension {dim} must be divisible by {subspaces} subspaces"
+        );
+
+        let sub_dim = dim / subspaces;
+        let centroids = sample.len().min(CENTROIDS);
+
+        let codebooks = (0..subspaces)
+            .into_par_iter()
+            .flat_map_iter(|s| {
+                let mut rng = StdRng::seed_from_u64(seed ^ s as u64);
+                let subvectors = sample
+                    .iter()
+                    .map(|v| &v[s * sub_dim..(s + 1) * sub_dim])
+                    .collect::<Vec<_>>();
+
+                kmeans(&subvectors, centroids, iterations, &mut rng)
+            })
+            .collect::<Vec<_>>();
+
+        let table = codebooks
+            .chunks_exact(centroids * sub_dim)
+            .flat_map(|codebook| {
+                let codebook = codebook.chunks_exact(sub_dim).collect::<Vec<_>>();
+                codebook
+                    .iter()
+                    .flat_map(|a| codebook.iter().map(|b| Euclidean::distance(a, b)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Self {
+            dim,
+            subspaces,
+            centroids,
+            codebooks,
+            table,
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn subspaces(&self) -> usize {
+        self.subspaces
+    }
+
+    fn codebook(&self, subspace: usize) -> impl Iterator<Item = &[f32]> {
+        let len = self.centroids * self.dim / self.subspaces;
+        self.codebooks[subspace * len..(subspace + 1) * len].chunks_exact(self.dim / self.subspaces)
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Box<[u8]> {
+        assert_eq!(vector.len(), self.dim, "vector has the wrong dimension");
+
+        vector
+            .chunks_exact(self.dim / self.subspaces)
+            .enumerate()
+            .map(|(s, subvector)| nearest(self.codebook(s), subvector) as u8)
+            .collect()
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes
+            .iter()
+            .enumerate()
+            .flat_map(|(s, &code)| self.codebook(s).nth(code as usize).unwrap())
+            .copied()
+            .collect()
+    }
+
+    // Distances from the subvectors of `vector` to every centroid, for asymmetric distance between
+    // the exact query and encoded points
+    pub fn query(&self, vector: &[f32]) -> QueryTable {
+        assert_eq!(vector.len(), self.dim, "vector has the wrong dimension");
+
+        QueryTable {
+            centroids: self.centroids,
+            table: vector
+                .chunks_exact(self.dim / self.subspaces)
+                .enumerate()
+                .flat_map(|(s, subvector)| {
+                    self.codebook(s)
+                        .map(|centroid| Euclidean::distance(subvector, centroid))
+                })
+                .collect(),
+        }
+    }
+}
+
+// Codec shared by every `PQPoint` of a kind, such that points only hold their codes. Implemented by
+// a type naming the codec, which is trained once and kept e.g. in a `OnceLock`.
+pub trait Codec: 'static {
+    fn pq() -> &'static PQ;
+}
+
+// Vector encoded by the `PQ` codec of `C` into `M` codes, one for every subspace
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct PQPoint<C, const M: usize> {
+    #[serde(with = "codes")]
+    codes: [u8; M],
+    #[serde(skip)]
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec, const M: usize> PQPoint<C, M> {
+    pub fn new(vector: &[f32]) -> Self {
+        let pq = C::pq();
+        assert_eq!(pq.subspaces, M, "codec has {} subspaces", pq.subspaces);
+
+        let mut codes = [0; M];
+        codes.copy_from_slice(&pq.encode(vector));
+        Self {
+            codes,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn codes(&self) -> &[u8; M] {
+        &self.codes
+    }
+
+    pub fn decode(&self) -> Vec<f32> {
+        C::pq().decode(&self.codes)
+    }
+}
+
+impl<C, const M: usize> Clone for PQPoint<C, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C, const M: usize> Copy for PQPoint<C, M> {}
+
+impl<C, const M: usize> fmt::Debug for PQPoint<C, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PQPoint")
+            .field("codes", &self.codes)
+            .finish()
+    }
+}
+
+// Symmetric distance between the centroids of both points
+impl<C: Codec, const M: usize> Point for PQPoint<C, M> {
+    #[inline(always)]
+    fn distance(&self, other: &Self) -> usize {
+        #[cfg(feature = "instrument")]
+        trace!("distance");
+
+        let pq = C::pq();
+        let centroids = pq.centroids;
+        let distance = self
+            .codes
+            .iter()
+            .zip(other.codes.iter())
+            .enumerate()
+            .map(|(s, (&a, &b))| pq.table[(s * centroids + a as usize) * centroids + b as usize])
+            .sum();
+
+        distance_key(distance)
+    }
+}
+
+// Codes as a tuple of bytes, as serde only implements arrays of up to 32 elements
+mod codes {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const M: usize>(
+        codes: &[u8; M],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(M)?;
+        for code in codes {
+            tuple.serialize_element(code)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const M: usize>(
+        deserializer: D,
+    ) -> Result<[u8; M], D::Error> {
+        struct CodesVisitor<const M: usize>;
+
+        impl<'de, const M: usize> Visitor<'de> for CodesVisitor<M> {
+            type Value = [u8; M];
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{M} codes")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; M], A::Error> {
+                let mut codes = [0; M];
+                for (i, code) in codes.iter_mut().enumerate() {
+                    *code = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                Ok(codes)
+            }
+        }
+
+        deserializer.deserialize_tuple(M, CodesVisitor)
+    }
+}
+
+pub struct QueryTable {
+    centroids: usize,
+    table: Vec<f32>,
+}
+
+impl QueryTable {
+    // Asymmetric distance between the query and the centroids of `point`
+    #[inline(always)]
+    pub fn distance<C, const M: usize>(&self, point: &PQPoint<C, M>) -> usize {
+        #[cfg(feature = "instrument")]
+        trace!("distance");
+
+        let distance = point
+            .codes
+            .iter()
+            .enumerate()
+            .map(|(s, &code)| self.table[s * self.centroids + code as usize])
+            .sum();
+
+        distance_key(distance)
+    }
+}
+
+fn nearest<'a>(centroids: impl Iterator<Item = &'a [f32]>, vector: &[f32]) -> usize {
+    centroids
+        .map(|centroid| Euclidean::distance(vector, centroid))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+        .expect("there is at least one centroid")
+}
+
+// Centroids of `k` clusters of the vectors, concatenated
+fn kmeans(vectors: &[&[f32]], k: usize, iterations: usize, rng: &mut StdRng) -> Vec<f32> {
+    let dim = vectors[0].len();
+    let mut centroids = index::sample(rng, vectors.len(), k)
+        .into_iter()
+        .flat_map(|i| vectors[i])
+        .copied()
+        .collect::<Vec<_>>();
+
+    for _ in 0..iterations {
+        let mut sums = vec![0.0; k * dim];
+        let mut counts = vec![0usize; k];
+
+        for vector in vectors {
+            let c = nearest(centroids.chunks_exact(dim), vector);
+            counts[c] += 1;
+            for (sum, x) in sums[c * dim..(c + 1) * dim].iter_mut().zip(*vector) {
+                *sum += x;
+            }
+        }
+
+        // Centroids of empty clusters are kept
+        for ((centroid, sum), &count) in centroids
+            .chunks_exact_mut(dim)
+            .zip(sums.chunks_exact(dim))
+            .zip(&counts)
+        {
+            if count > 0 {
+                for (c, s) in centroid.iter_mut().zip(sum) {
+                    *c = s / count as f32;
+                }
+            }
+        }
+    }
+
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use hnsw_itu::{HNSWBuilder, IndexBuilder, NSWOptions, HNSW};
+    use rand::Rng;
+
+    use super::*;
+    use crate::distance_from_key;
+
+    static PQ: OnceLock<PQ> = OnceLock::new();
+
+    struct TestCodec;
+
+    impl Codec for TestCodec {
+        fn pq() -> &'static PQ {
+            PQ.get_or_init(|| PQ::train(&random_vectors(500, 8), 4, 10, 0))
+        }
+    }
+
+    type TestPoint = PQPoint<TestCodec, 4>;
+
+    fn random_vectors(len: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..len)
+            .map(|_| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn encode_decode() {
+        let vectors = random_vectors(300, 8);
+        let pq = PQ::train(&vectors, 4, 10, 0);
+        assert_eq!((pq.dim(), pq.subspaces()), (8, 4));
+
+        let codes = pq.encode(&vectors[0]);
+        assert_eq!(codes.len(), 4);
+
+        // With 256 centroids for 300 vectors most are reconstructed almost exactly
+        let error = vectors
+            .iter()
+            .map(|v| Euclidean::distance(v, &pq.decode(&pq.encode(v))))
+            .sum::<f32>()
+            / vectors.len() as f32;
+        assert!(error < 0.05, "mean squared error {error}");
+    }
+
+    #[test]
+    fn symmetric_and_asymmetric_distance() {
+        let vectors = random_vectors(500, 8);
+        let pq = TestCodec::pq();
+        let points = vectors
+            .iter()
+            .map(|v| TestPoint::new(v))
+            .collect::<Vec<_>>();
+        // Points are no larger than their codes
+        assert_eq!(std::mem::size_of::<TestPoint>(), 4);
+
+        for (i, j) in [(0, 1), (2, 3), (4, 4)] {
+            let symmetric = distance_from_key(points[i].distance(&points[j]));
+            let decoded = Euclidean::distance(&points[i].decode(), &points[j].decode());
+            assert!((symmetric - decoded).abs() < 1e-4);
+
+            let asymmetric = distance_from_key(pq.query(&vectors[i]).distance(&points[j]));
+            let exact = Euclidean::distance(&vectors[i], &points[j].decode());
+            assert!((asymmetric - exact).abs() < 1e-4);
+        }
+
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            size: points.len(),
+            ..NSWOptions::default()
+        });
+        builder.extend(points);
+        let hnsw = builder.build();
+
+        let query = pq.query(&vectors[7]);
+        let res = hnsw.search_by(&query, 1, 64, |p, q| q.distance(p));
+        assert_eq!(res[0].key, 7);
+
+        // Indexes of points can be written, and read back with the codec of their type
+        let hnsw: HNSW<TestPoint> =
+            bincode::deserialize(&bincode::serialize(&hnsw).unwrap()).unwrap();
+        let res = hnsw.search_by(&query, 1, 64, |p, q| q.distance(p));
+        assert_eq!(res[0].key, 7);
+    }
+}
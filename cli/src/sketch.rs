@@ -1,4 +1,4 @@
-use hnsw_itu::Point;
+use hnsw_itu::{Centroid, Error, Point};
 use ndarray::{arr1, Array1};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "instrument")]
@@ -10,6 +10,9 @@ pub struct Sketch {
 }
 
 impl Sketch {
+    // Number of u64 words in a sketch
+    pub const WORDS: usize = 16;
+
     pub const fn new(data: [u64; 16]) -> Self {
         Self { data }
     }
@@ -26,15 +29,80 @@ impl Point for Sketch {
             .zip(other.data.iter())
             .fold(0, |acc, (lhs, rhs)| acc + (lhs ^ rhs).count_ones() as usize)
     }
+
+    // Checks the bound every 256 bits to keep the inner loop vectorizable
+    #[inline(always)]
+    fn distance_bounded(&self, other: &Self, bound: usize) -> usize {
+        #[cfg(feature = "instrument")]
+        trace!("distance");
+
+        let mut distance = 0;
+        for (lhs, rhs) in self.data.chunks_exact(4).zip(other.data.chunks_exact(4)) {
+            distance += lhs
+                .iter()
+                .zip(rhs)
+                .fold(0, |acc, (lhs, rhs)| acc + (lhs ^ rhs).count_ones() as usize);
+
+            if distance > bound {
+                break;
+            }
+        }
+
+        distance
+    }
 }
 
-// It's just easier to panic than TryFrom
+// k-majority: each bit is set if it is set in the majority of the points
+impl Centroid for Sketch {
+    fn centroid<'a>(points: impl IntoIterator<Item = &'a Self>) -> Option<Self> {
+        let mut counts = [[0usize; 64]; 16];
+        let mut len = 0;
+
+        for point in points {
+            len += 1;
+            for (count, word) in counts.iter_mut().zip(point.data) {
+                for (bit, c) in count.iter_mut().enumerate() {
+                    *c += (word >> bit) as usize & 1;
+                }
+            }
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        let mut data = [0; 16];
+        for (word, count) in data.iter_mut().zip(counts) {
+            for (bit, c) in count.into_iter().enumerate() {
+                if c * 2 > len {
+                    *word |= 1 << bit;
+                }
+            }
+        }
+
+        Some(Self::new(data))
+    }
+}
+
+impl TryFrom<&[u64]> for Sketch {
+    type Error = Error;
+
+    fn try_from(value: &[u64]) -> Result<Self, Self::Error> {
+        let data = value.try_into().map_err(|_| Error::Dimension {
+            expected: Self::WORDS,
+            actual: value.len(),
+        })?;
+
+        Ok(Self::new(data))
+    }
+}
+
+// Panics on rows of the wrong width, which readers of sketch datasets check up front. Use
+// `Sketch::try_from` to handle them.
 impl From<Array1<u64>> for Sketch {
     fn from(value: Array1<u64>) -> Self {
-        Self::new([
-            value[0], value[1], value[2], value[3], value[4], value[5], value[6], value[7],
-            value[8], value[9], value[10], value[11], value[12], value[13], value[14], value[15],
-        ])
+        let row = value.to_vec();
+        Self::try_from(row.as_slice()).expect("row has the wrong number of words")
     }
 }
 
@@ -56,4 +124,40 @@ mod tests {
 
         assert_eq!(a.distance(&b), 5);
     }
+
+    #[test]
+    fn from_words() {
+        let words = (0..16).collect::<Vec<u64>>();
+        assert_eq!(Sketch::try_from(words.as_slice()).unwrap().data[15], 15);
+        assert_eq!(
+            Sketch::try_from(&words[..8]).unwrap_err(),
+            Error::Dimension {
+                expected: 16,
+                actual: 8
+            }
+        );
+    }
+
+    #[test]
+    fn bounded_hamming_distance() {
+        let a = Sketch::new([u64::MAX; 16]);
+        let b = Sketch::new([0; 16]);
+
+        assert_eq!(a.distance_bounded(&b, usize::MAX), 1024);
+        assert!(a.distance_bounded(&b, 300) > 300);
+        assert!(a.distance_bounded(&b, 300) < 1024);
+    }
+
+    #[test]
+    fn majority_centroid() {
+        let a = Sketch::new([0b0111, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1 << 63]);
+        let b = Sketch::new([0b0110, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1 << 63]);
+        let c = Sketch::new([0b1100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let centroid = Sketch::centroid([&a, &b, &c]).unwrap();
+
+        assert_eq!(centroid.data[0], 0b0110);
+        assert_eq!(centroid.data[15], 1 << 63);
+        assert!(Sketch::centroid([]).is_none());
+    }
 }
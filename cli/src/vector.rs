@@ -0,0 +1,186 @@
+use std::marker::PhantomData;
+
+use hnsw_itu::{Centroid, Point};
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "instrument")]
+use tracing::trace;
+
+pub trait Metric {
+    fn distance(lhs: &[f32], rhs: &[f32]) -> f32;
+}
+
+// Squared euclidean distance, which has the same order as the euclidean distance
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+// 1 - cosine similarity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Cosine;
+
+// Negated inner product, so larger products are nearer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InnerProduct;
+
+impl Metric for Euclidean {
+    fn distance(lhs: &[f32], rhs: &[f32]) -> f32 {
+        lhs.iter().zip(rhs).map(|(l, r)| (l - r) * (l - r)).sum()
+    }
+}
+
+impl Metric for Cosine {
+    fn distance(lhs: &[f32], rhs: &[f32]) -> f32 {
+        let (mut dot, mut lhs_norm, mut rhs_norm) = (0.0, 0.0, 0.0);
+        for (l, r) in lhs.iter().zip(rhs) {
+            dot += l * r;
+            lhs_norm += l * l;
+            rhs_norm += r * r;
+        }
+
+        let norm = (lhs_norm * rhs_norm).sqrt();
+        if norm == 0.0 {
+            1.0
+        } else {
+            1.0 - dot / norm
+        }
+    }
+}
+
+impl Metric for InnerProduct {
+    fn distance(lhs: &[f32], rhs: &[f32]) -> f32 {
+        -lhs.iter().zip(rhs).map(|(l, r)| l * r).sum::<f32>()
+    }
+}
+
+// Distances are `usize`, so float distances are mapped to integers of the same order. Positive
+// floats are ordered like their bits, negative floats reversed, and positive above negative.
+pub fn distance_key(distance: f32) -> usize {
+    let bits = distance.to_bits();
+    let key = if bits >> 31 == 1 {
+        !bits
+    } else {
+        bits | 1 << 31
+    };
+    key as usize
+}
+
+pub fn distance_from_key(key: usize) -> f32 {
+    let key = key as u32;
+    let bits = if key >> 31 == 1 {
+        key & !(1 << 31)
+    } else {
+        !key
+    };
+    f32::from_bits(bits)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound = "")]
+pub struct Vector<M> {
+    pub data: Vec<f32>,
+    #[serde(skip)]
+    _metric: PhantomData<M>,
+}
+
+impl<M> Vector<M> {
+    pub const fn new(data: Vec<f32>) -> Self {
+        Self {
+            data,
+            _metric: PhantomData,
+        }
+    }
+}
+
+impl<M: Metric> Point for Vector<M> {
+    #[inline(always)]
+    fn distance(&self, other: &Self) -> usize {
+        #[cfg(feature = "instrument")]
+        trace!("distance");
+
+        distance_key(M::distance(&self.data, &other.data))
+    }
+}
+
+// Mean of the vectors
+impl<M> Centroid for Vector<M> {
+    fn centroid<'a>(points: impl IntoIterator<Item = &'a Self>) -> Option<Self>
+    where
+        Self: 'a,
+    {
+        let mut points = points.into_iter();
+        let mut sum = points.next()?.data.clone();
+        let mut len = 1;
+
+        for point in points {
+            len += 1;
+            for (s, x) in sum.iter_mut().zip(&point.data) {
+                *s += x;
+            }
+        }
+
+        sum.iter_mut().for_each(|s| *s /= len as f32);
+        Some(Self::new(sum))
+    }
+}
+
+impl<M> From<Array1<f32>> for Vector<M> {
+    fn from(value: Array1<f32>) -> Self {
+        Self::new(value.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_keys_are_ordered() {
+        let distances = [
+            f32::NEG_INFINITY,
+            -3.5,
+            -1.0,
+            -0.0,
+            0.0,
+            1e-10,
+            1.0,
+            2.5,
+            f32::INFINITY,
+        ];
+
+        for pair in distances.windows(2) {
+            assert!(distance_key(pair[0]) <= distance_key(pair[1]), "{pair:?}");
+        }
+        for d in distances {
+            assert_eq!(distance_from_key(distance_key(d)), d);
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let a = Vector::<Euclidean>::new(vec![1.0, 0.0]);
+        let b = Vector::<Euclidean>::new(vec![0.0, 2.0]);
+        assert_eq!(distance_from_key(a.distance(&b)), 5.0);
+
+        let a = Vector::<Cosine>::new(vec![1.0, 0.0]);
+        let b = Vector::<Cosine>::new(vec![0.0, 2.0]);
+        let c = Vector::<Cosine>::new(vec![3.0, 0.0]);
+        assert_eq!(distance_from_key(a.distance(&b)), 1.0);
+        assert_eq!(distance_from_key(a.distance(&c)), 0.0);
+
+        let a = Vector::<InnerProduct>::new(vec![1.0, 2.0]);
+        let b = Vector::<InnerProduct>::new(vec![3.0, 4.0]);
+        let c = Vector::<InnerProduct>::new(vec![-3.0, 4.0]);
+        assert_eq!(distance_from_key(a.distance(&b)), -11.0);
+        assert!(a.distance(&b) < a.distance(&c));
+    }
+
+    #[test]
+    fn mean_centroid() {
+        let points = [
+            Vector::<Euclidean>::new(vec![1.0, 2.0]),
+            Vector::new(vec![3.0, 6.0]),
+        ];
+        assert_eq!(Vector::centroid(&points), Some(Vector::new(vec![2.0, 4.0])));
+        assert_eq!(Vector::<Euclidean>::centroid(&[]), None);
+    }
+}
@@ -23,6 +23,7 @@ fn main() {
         ef_construction: 24,
         max_connections: 32,
         size: points.len(),
+        ..NSWOptions::default()
     });
 
     // Add dataset to graph
@@ -1,6 +1,8 @@
-use crate::{Idx, Reset, Set};
+use crate::{Error, Idx, IdxExt, Reset, Result, Set};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::warn;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -14,17 +16,49 @@ impl BitSet {
             bits: vec![0; n.div_ceil(std::mem::size_of::<usize>())],
         }
     }
+
+    // Number of elements the set can hold
+    pub fn capacity(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<usize>()
+    }
+
+    // Like `insert`, but returns an error instead of ignoring `t` when it is out of range
+    pub fn try_insert(&mut self, t: Idx) -> Result<()> {
+        if t.into_usize() >= self.capacity() {
+            return Err(Error::OutOfRange {
+                index: t.into_usize(),
+                len: self.capacity(),
+            });
+        }
+
+        self.insert(t);
+        Ok(())
+    }
 }
 
 impl Set<Idx> for BitSet {
+    // Elements out of range are logged and not inserted
     fn insert(&mut self, t: Idx) {
-        let s = std::mem::size_of::<Idx>();
-        self.bits[t / s] |= 1 << (t % s);
+        let s = std::mem::size_of::<usize>();
+        let t = t.into_usize();
+        let Some(bits) = self.bits.get_mut(t / s) else {
+            #[cfg(feature = "tracing")]
+            warn!(
+                t,
+                capacity = self.capacity(),
+                "Not inserting element out of range"
+            );
+            return;
+        };
+        *bits |= 1 << (t % s);
     }
 
     fn contains(&self, t: Idx) -> bool {
-        let s = std::mem::size_of::<Idx>();
-        self.bits[t / s] & (1 << (t % s)) != 0
+        let s = std::mem::size_of::<usize>();
+        let t = t.into_usize();
+        self.bits
+            .get(t / s)
+            .is_some_and(|bits| bits & (1 << (t % s)) != 0)
     }
 
     fn len(&self) -> usize {
@@ -55,17 +89,31 @@ mod tests {
     #[test]
     fn test_bitset() {
         let mut set = BitSet::new(100);
-        set.insert(3usize);
-        set.insert(93usize);
-        assert!(set.contains(3usize));
-        assert!(set.contains(93usize));
-        assert!(!set.contains(67usize));
-        assert!(!set.contains(29usize));
-        assert!(!set.contains(30usize));
+        set.insert(3);
+        set.insert(93);
+        assert!(set.contains(3));
+        assert!(set.contains(93));
+        assert!(!set.contains(67));
+        assert!(!set.contains(29));
+        assert!(!set.contains(30));
 
         set.reset();
 
-        assert!(!set.contains(3usize));
-        assert!(!set.contains(93usize));
+        assert!(!set.contains(3));
+        assert!(!set.contains(93));
+
+        assert!(set.try_insert(99).is_ok());
+        assert_eq!(
+            set.try_insert(Idx::from_usize(set.capacity())),
+            Err(Error::OutOfRange {
+                index: set.capacity(),
+                len: set.capacity()
+            })
+        );
+
+        let len = set.len();
+        set.insert(Idx::from_usize(set.capacity()));
+        assert_eq!(set.len(), len);
+        assert!(!set.contains(Idx::from_usize(set.capacity())));
     }
 }
@@ -8,7 +8,33 @@ pub use crate::bitset::*;
 pub use crate::generationset::*;
 pub use crate::simplegraph::*;
 
+// Index of a node. With the `idx-u32` feature indices are 32-bit, which halves the memory of every
+// edge on 64-bit machines but limits graphs to `u32::MAX` nodes.
+#[cfg(not(feature = "idx-u32"))]
 pub type Idx = usize;
+#[cfg(feature = "idx-u32")]
+pub type Idx = u32;
+
+// Conversions between indices and positions in vectors, which are the same type unless the
+// `idx-u32` feature is enabled
+pub trait IdxExt {
+    fn from_usize(i: usize) -> Self;
+    fn into_usize(self) -> usize;
+}
+
+#[allow(clippy::unnecessary_cast)]
+impl IdxExt for Idx {
+    #[inline(always)]
+    fn from_usize(i: usize) -> Self {
+        assert!(i <= Idx::MAX as usize, "{i} does not fit in a node index");
+        i as Idx
+    }
+
+    #[inline(always)]
+    fn into_usize(self) -> usize {
+        self as usize
+    }
+}
 
 pub trait Graph<T> {
     fn add(&mut self, t: T) -> Idx;
@@ -49,6 +75,16 @@ pub trait Graph<T> {
     }
 }
 
+// Graph as seen by searches, which visit neighborhoods through a callback such that they can be
+// guarded by locks while they are visited
+pub(crate) trait SearchGraph<T> {
+    fn node(&self, v: Idx) -> Option<&T>;
+
+    fn visit_neighborhood<E>(&self, v: Idx, f: impl FnMut(Idx) -> Result<(), E>) -> Result<(), E>;
+
+    fn node_count(&self) -> usize;
+}
+
 pub trait MinK: Iterator {
     fn min_k(mut self, k: usize) -> Vec<Self::Item>
     where
@@ -84,7 +120,7 @@ pub trait Reset {
 
 pub trait Set<T>
 where
-    T: Into<usize> + Clone,
+    T: Clone,
 {
     fn insert(&mut self, t: T);
 
@@ -3,7 +3,7 @@ use std::collections::HashSet;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::{Graph, Idx};
+use crate::{Graph, Idx, IdxExt, SearchGraph};
 
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -21,6 +21,17 @@ impl<T> SimpleGraph<T> {
     pub fn adj_lists(&self) -> &Vec<HashSet<Idx>> {
         &self.adj_lists
     }
+
+    // Graph with the connections of every node as they are given, which need not be undirected,
+    // e.g. to verify the connections of another graph
+    pub(crate) fn from_adj_lists(nodes: Vec<T>, adj_lists: Vec<HashSet<Idx>>) -> Self {
+        debug_assert_eq!(nodes.len(), adj_lists.len());
+        Self {
+            nodes,
+            adj_lists,
+            empty: HashSet::default(),
+        }
+    }
 }
 
 impl<T> SimpleGraph<T> {
@@ -30,17 +41,17 @@ impl<T> SimpleGraph<T> {
 
     fn is_in_bounds(&self, v: Idx, w: Idx) -> bool {
         let len = self.adj_lists.len();
-        v < len && w < len
+        v.into_usize() < len && w.into_usize() < len
     }
 
     fn connect_directed(&mut self, src: Idx, target: Idx) {
-        if let Some(set) = self.adj_lists.get_mut(src) {
+        if let Some(set) = self.adj_lists.get_mut(src.into_usize()) {
             set.insert(target);
         }
     }
 
     fn disconnect_directed(&mut self, src: Idx, target: Idx) {
-        if let Some(set) = self.adj_lists.get_mut(src) {
+        if let Some(set) = self.adj_lists.get_mut(src.into_usize()) {
             set.remove(&target);
         }
     }
@@ -78,16 +89,40 @@ impl<T> FromIterator<T> for SimpleGraph<T> {
     }
 }
 
+impl<T> SearchGraph<T> for SimpleGraph<T> {
+    #[inline(always)]
+    fn node(&self, v: Idx) -> Option<&T> {
+        self.get(v)
+    }
+
+    #[inline(always)]
+    fn visit_neighborhood<E>(
+        &self,
+        v: Idx,
+        mut f: impl FnMut(Idx) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for &w in self.neighborhood(v) {
+            f(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn node_count(&self) -> usize {
+        self.size()
+    }
+}
+
 impl<T> Graph<T> for SimpleGraph<T> {
     fn add(&mut self, t: T) -> Idx {
-        let idx = self.nodes.len();
+        let idx = Idx::from_usize(self.nodes.len());
         self.nodes.push(t);
         self.adj_lists.push(HashSet::new());
         idx
     }
 
     fn get(&self, v: Idx) -> Option<&T> {
-        self.nodes.get(v)
+        self.nodes.get(v.into_usize())
     }
 
     fn add_edge(&mut self, v: Idx, w: Idx) {
@@ -109,7 +144,7 @@ impl<T> Graph<T> for SimpleGraph<T> {
     }
 
     fn neighborhood(&self, v: Idx) -> impl Iterator<Item = &Idx> {
-        if let Some(set) = self.adj_lists.get(v) {
+        if let Some(set) = self.adj_lists.get(v.into_usize()) {
             return set.iter();
         }
 
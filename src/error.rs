@@ -0,0 +1,46 @@
+use std::fmt::{self, Display};
+
+use crate::Idx;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // Search or construction parameter has a value that can not be used, e.g. `ef` of 0
+    InvalidParameter {
+        name: &'static str,
+        reason: &'static str,
+    },
+    // Graph refers to a node that is not in it, e.g. in a corrupted index file
+    NodeNotFound(Idx),
+    // Element is outside the range of a collection
+    OutOfRange {
+        index: usize,
+        len: usize,
+    },
+    // Point has a different number of elements than expected
+    Dimension {
+        expected: usize,
+        actual: usize,
+    },
+    // Build was stopped by its cancellation token
+    Cancelled,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParameter { name, reason } => write!(f, "invalid `{name}`: {reason}"),
+            Self::NodeNotFound(idx) => write!(f, "node {idx} is not in the graph"),
+            Self::OutOfRange { index, len } => {
+                write!(f, "index {index} is out of range for length {len}")
+            }
+            Self::Dimension { expected, actual } => {
+                write!(f, "expected {expected} elements, got {actual}")
+            }
+            Self::Cancelled => write!(f, "build was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,13 +1,24 @@
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::{Distance, IndexBuilder, MinK, Point};
+use crate::{Distance, Idx, IdxExt, IndexBuilder, Point, Result};
 
 use super::Index;
 
+// Number of queries and points evaluated together in `knns`, chosen such that a tile of points
+// stays in cache while it is compared against every query in a tile of queries
+const QUERY_TILE: usize = 64;
+const POINT_TILE: usize = 4096;
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Bruteforce<P> {
     points: Vec<P>,
+    // Missing from indexes serialized before ties were returned, which formats that tell a missing
+    // field apart read as `false`. Bincode can not, so index files of the CLI are rejected by their
+    // format version instead.
+    #[cfg_attr(feature = "serde", serde(default))]
+    ties: bool,
 }
 
 impl<P> Default for Bruteforce<P> {
@@ -18,7 +29,42 @@ impl<P> Default for Bruteforce<P> {
 
 impl<P> Bruteforce<P> {
     pub const fn new() -> Self {
-        Self { points: vec![] }
+        Self {
+            points: vec![],
+            ties: false,
+        }
+    }
+
+    // Return all points tied with the k-th nearest neighbor, so results can be longer than `k`
+    pub fn with_ties(mut self, ties: bool) -> Self {
+        self.ties = ties;
+        self
+    }
+
+    // Push the points nearer than the bound of `top` onto it, leaving out those for which `skip` is
+    // true
+    fn scan<'a>(
+        &'a self,
+        query: &P,
+        offset: usize,
+        points: &'a [P],
+        top: &mut TopK<'a, P>,
+        skip: impl Fn(Idx) -> bool,
+    ) where
+        P: Point,
+    {
+        for (i, point) in points.iter().enumerate() {
+            if skip(Idx::from_usize(offset + i)) {
+                continue;
+            }
+
+            let bound = top.bound();
+            let distance = query.distance_bounded(point, bound);
+
+            if distance <= bound {
+                top.push(Distance::new(distance, Idx::from_usize(offset + i), point));
+            }
+        }
     }
 }
 
@@ -39,16 +85,119 @@ impl<P> Index<P> for Bruteforce<P> {
     where
         P: Point,
     {
-        self.points
-            .iter()
-            .enumerate()
-            .map(|(key, point)| Distance::new(query.distance(point), key, point))
-            .min_k(k)
+        let mut top = TopK::new(k, self.ties);
+        self.scan(query, 0, &self.points, &mut top, |_| false);
+        top.into_sorted_vec()
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        _ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let mut top = TopK::new(k, self.ties);
+        self.scan(query, 0, &self.points, &mut top, skip);
+        Ok(top.into_sorted_vec())
     }
 
     fn size(&self) -> usize {
         self.points.len()
     }
+
+    // Evaluates tiles of queries against tiles of points
+    fn knns<I>(&self, queries: I, k: usize, _ef: usize) -> Vec<Vec<Distance<'_, P>>>
+    where
+        Self: Sync,
+        I: IntoIterator<Item = P>,
+        P: Point + Sync,
+    {
+        queries
+            .into_iter()
+            .collect::<Vec<_>>()
+            .par_chunks(QUERY_TILE)
+            .flat_map_iter(|queries| {
+                let mut tops = queries
+                    .iter()
+                    .map(|_| TopK::new(k, self.ties))
+                    .collect::<Vec<_>>();
+
+                for (i, points) in self.points.chunks(POINT_TILE).enumerate() {
+                    for (query, top) in queries.iter().zip(tops.iter_mut()) {
+                        self.scan(query, i * POINT_TILE, points, top, |_| false);
+                    }
+                }
+
+                tops.into_iter().map(TopK::into_sorted_vec)
+            })
+            .collect()
+    }
+}
+
+// The k smallest distances seen so far, and optionally everything tied with the k-th. Candidates
+// are buffered and only pruned when the buffer grows too large.
+struct TopK<'a, P> {
+    k: usize,
+    ties: bool,
+    bound: usize,
+    capacity: usize,
+    items: Vec<Distance<'a, P>>,
+}
+
+impl<'a, P> TopK<'a, P> {
+    fn new(k: usize, ties: bool) -> Self {
+        Self {
+            k,
+            ties,
+            bound: usize::MAX,
+            capacity: 2 * k.max(1),
+            items: Vec::with_capacity(2 * k.max(1)),
+        }
+    }
+
+    fn bound(&self) -> usize {
+        self.bound
+    }
+
+    fn push(&mut self, distance: Distance<'a, P>) {
+        if self.k == 0 {
+            return;
+        }
+
+        self.items.push(distance);
+
+        if self.items.len() >= self.capacity {
+            self.prune();
+            // Lots of ties, grow the buffer to avoid pruning on every push
+            self.capacity = self.capacity.max(2 * self.items.len());
+        }
+    }
+
+    fn prune(&mut self) {
+        if self.items.len() <= self.k {
+            return;
+        }
+
+        self.items.select_nth_unstable(self.k - 1);
+        self.bound = self.items[self.k - 1].distance;
+
+        if self.ties {
+            let bound = self.bound;
+            self.items.retain(|d| d.distance <= bound);
+        } else {
+            self.items.truncate(self.k);
+        }
+    }
+
+    fn into_sorted_vec(mut self) -> Vec<Distance<'a, P>> {
+        self.prune();
+        self.items.sort();
+        self.items
+    }
 }
 
 impl<P> FromIterator<P> for Bruteforce<P> {
@@ -68,3 +217,44 @@ impl<P> Extend<P> for Bruteforce<P> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(res: Vec<Distance<'_, i32>>) -> Vec<Idx> {
+        res.into_iter().map(|d| d.key).collect()
+    }
+
+    #[test]
+    fn test_bruteforce() {
+        let bruteforce = (0..10_000).rev().collect::<Bruteforce<_>>();
+
+        let res = bruteforce.search(&5, 4, 0);
+        assert_eq!(
+            res.iter().map(|d| *d.point).collect::<Vec<_>>(),
+            vec![5, 6, 4, 7]
+        );
+
+        let queries = vec![5, 9_000, -3, 5_000];
+        let knns = bruteforce.knns(queries.clone(), 10, 0);
+        for (q, res) in queries.iter().zip(knns) {
+            assert_eq!(keys(res), keys(bruteforce.search(q, 10, 0)));
+        }
+    }
+
+    #[test]
+    fn test_ties() {
+        let points = vec![0, 1, -1, 2, -2, 3, -3, 1, -1];
+        let bruteforce = points.iter().copied().collect::<Bruteforce<_>>();
+        assert_eq!(keys(bruteforce.search(&0, 2, 0)), vec![0, 1]);
+
+        let bruteforce = bruteforce.with_ties(true);
+        assert_eq!(keys(bruteforce.search(&0, 2, 0)), vec![0, 1, 2, 7, 8]);
+        assert_eq!(
+            keys(bruteforce.knns([0], 2, 0).remove(0)),
+            vec![0, 1, 2, 7, 8]
+        );
+        assert_eq!(bruteforce.search(&0, 0, 0).len(), 0);
+    }
+}
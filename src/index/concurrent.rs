@@ -0,0 +1,386 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock, RwLock, RwLockWriteGuard,
+    },
+};
+
+use crate::{
+    nsw::{self, prune_neighbors, search_select_neighbors, validate_options},
+    or_empty,
+    verify::verify_layer,
+    Distance, Error, Idx, IdxExt, Index, NSWOptions, Point, Result, SearchGraph, SimpleGraph,
+    Violation,
+};
+
+// NSW graph that is searched and inserted into at the same time from many threads. Every node has
+// its own lock over its connections, which is only held while the connections are read or changed.
+// An edge is added and pruned with the locks of both of its ends held, so edges stay undirected and
+// every node is bounded to `max_connections`, but an insert adds its edges one at a time, so
+// searches can see some of the edges of a point being inserted but not others. Points are never
+// moved, so storage for `size` points is allocated up front, which is the capacity of the index and
+// must be positive, as `NSWOptions::default()` leaves it at 0.
+pub struct ConcurrentNSW<P> {
+    points: Box<[OnceLock<P>]>,
+    adj_lists: Box<[RwLock<Vec<Idx>>]>,
+    // Number of nodes that have been given an index
+    reserved: AtomicUsize,
+    // Number of nodes that have been inserted
+    inserted: AtomicUsize,
+    ep: OnceLock<Idx>,
+    ef_construction: usize,
+    connections: usize,
+    max_connections: usize,
+}
+
+impl<P> ConcurrentNSW<P> {
+    pub fn new(options: NSWOptions) -> Self {
+        Self {
+            points: (0..options.size).map(|_| OnceLock::new()).collect(),
+            adj_lists: (0..options.size).map(|_| RwLock::default()).collect(),
+            reserved: AtomicUsize::new(0),
+            inserted: AtomicUsize::new(0),
+            ep: OnceLock::new(),
+            ef_construction: options.ef_construction,
+            connections: options.connections,
+            max_connections: options.max_connections,
+        }
+    }
+
+    // Number of points the index can hold, which is `size` of the options it was created with
+    pub fn capacity(&self) -> usize {
+        self.points.len()
+    }
+
+    // Copy of the connections of `v`, as they are now
+    pub fn neighborhood(&self, v: Idx) -> Vec<Idx> {
+        self.adj_lists
+            .get(v.into_usize())
+            .map(|adj_list| adj_list.read().unwrap_or_else(|e| e.into_inner()).clone())
+            .unwrap_or_default()
+    }
+
+    // Broken invariants of the graph, of which the connections are read one node at a time, such
+    // that it is only consistent once no points are being inserted
+    pub fn verify(&self, max_connections: Option<usize>) -> Vec<Violation> {
+        let size = self.reserved.load(Ordering::Acquire);
+        let graph = SimpleGraph::from_adj_lists(
+            vec![(); size],
+            (0..size)
+                .map(|v| {
+                    self.neighborhood(Idx::from_usize(v))
+                        .into_iter()
+                        .collect::<HashSet<_>>()
+                })
+                .collect(),
+        );
+
+        let mut violations = vec![];
+        verify_layer(
+            &graph,
+            0,
+            self.ep.get().copied(),
+            max_connections,
+            &mut violations,
+        );
+        violations
+    }
+
+    // Write locks of the connections of `v` and `w`, which are taken in the order of the nodes, such
+    // that threads locking the same two nodes can not deadlock
+    fn lock_pair(
+        &self,
+        v: Idx,
+        w: Idx,
+    ) -> (
+        RwLockWriteGuard<'_, Vec<Idx>>,
+        RwLockWriteGuard<'_, Vec<Idx>>,
+    ) {
+        let lock = |v: Idx| {
+            self.adj_lists[v.into_usize()]
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+        };
+
+        if v < w {
+            let v_list = lock(v);
+            (v_list, lock(w))
+        } else {
+            let w_list = lock(w);
+            (lock(v), w_list)
+        }
+    }
+
+    // Remove the connection of `w` to `v` after `v` pruned its connection to `w`, unless `v` has been
+    // connected to `w` again since
+    fn disconnect_pruned(&self, v: Idx, w: Idx) {
+        let (v_list, mut w_list) = self.lock_pair(v, w);
+        if !v_list.contains(&w) {
+            w_list.retain(|&u| u != v);
+        }
+    }
+}
+
+impl<P: Point> ConcurrentNSW<P> {
+    pub fn insert(&self, point: P) -> Result<Idx> {
+        validate_options(self.ef_construction, self.connections)?;
+
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return Err(Error::InvalidParameter {
+                name: "size",
+                reason: "must be positive",
+            });
+        }
+        let idx = self
+            .reserved
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < capacity).then_some(len + 1)
+            })
+            .map_err(|len| Error::OutOfRange {
+                index: len,
+                len: capacity,
+            })?;
+        let point_idx = Idx::from_usize(idx);
+
+        // The point is set before any edge to it is added, so it is there for all searches
+        let _ = self.points[idx].set(point);
+        let point = self.node(point_idx).ok_or(Error::NodeNotFound(point_idx))?;
+
+        let ep = *self.ep.get_or_init(|| point_idx);
+        if ep != point_idx {
+            let neighbors = search_select_neighbors(
+                self,
+                point,
+                self.connections,
+                self.ef_construction,
+                ep,
+                &Point::distance,
+            )?;
+
+            self.insert_neighbors(point_idx, &neighbors)?;
+        }
+
+        self.inserted.fetch_add(1, Ordering::Release);
+        Ok(point_idx)
+    }
+
+    // Like `nsw::insert_neighbors`, but with both ends of every edge locked while it is added and
+    // pruned, and while an edge pruned at one end is removed at the other, such that edges stay
+    // undirected. At most two locks are held at a time.
+    fn insert_neighbors(&self, point_idx: Idx, neighbors: &[Idx]) -> Result<()> {
+        for &e in neighbors.iter().filter(|&&e| e != point_idx) {
+            for (v, pruned) in self.connect_pruned(point_idx, e)? {
+                for w in pruned {
+                    self.disconnect_pruned(v, w);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Connect `v` and `w` and prune the connections of both to `max_connections` while they are
+    // locked, such that concurrent inserts can not take them past the limit. The edge is removed
+    // from both if either prunes it. Returns every end with the other nodes it is no longer
+    // connected to.
+    fn connect_pruned(&self, v: Idx, w: Idx) -> Result<[(Idx, Vec<Idx>); 2]> {
+        let (mut v_list, mut w_list) = self.lock_pair(v, w);
+        if !v_list.contains(&w) {
+            v_list.push(w);
+        }
+        if !w_list.contains(&v) {
+            w_list.push(v);
+        }
+
+        let mut v_pruned = self.prune(v, &mut v_list)?;
+        let mut w_pruned = self.prune(w, &mut w_list)?;
+        if v_pruned.contains(&w) || w_pruned.contains(&v) {
+            v_list.retain(|&u| u != w);
+            w_list.retain(|&u| u != v);
+            v_pruned.retain(|&u| u != w);
+            w_pruned.retain(|&u| u != v);
+        }
+
+        Ok([(v, v_pruned), (w, w_pruned)])
+    }
+
+    // Prune the connections of `src` to `max_connections`, returning the nodes it is no longer
+    // connected to
+    fn prune(&self, src: Idx, adj_list: &mut Vec<Idx>) -> Result<Vec<Idx>> {
+        if adj_list.len() <= self.max_connections {
+            return Ok(vec![]);
+        }
+        let src_elem = self.node(src).ok_or(Error::NodeNotFound(src))?;
+
+        let keys = prune_neighbors(
+            src_elem,
+            adj_list.clone(),
+            |idx| self.node(idx),
+            self.max_connections,
+            Point::distance,
+        )?;
+        let removed = adj_list
+            .iter()
+            .copied()
+            .filter(|w| !keys.contains(w))
+            .collect();
+        *adj_list = keys;
+
+        Ok(removed)
+    }
+}
+
+impl<P> SearchGraph<P> for ConcurrentNSW<P> {
+    fn node(&self, v: Idx) -> Option<&P> {
+        self.points.get(v.into_usize())?.get()
+    }
+
+    // The connections are read locked while they are visited
+    fn visit_neighborhood<E>(
+        &self,
+        v: Idx,
+        mut f: impl FnMut(Idx) -> std::result::Result<(), E>,
+    ) -> std::result::Result<(), E> {
+        let Some(adj_list) = self.adj_lists.get(v.into_usize()) else {
+            return Ok(());
+        };
+
+        for &w in adj_list.read().unwrap_or_else(|e| e.into_inner()).iter() {
+            f(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn node_count(&self) -> usize {
+        self.inserted.load(Ordering::Acquire)
+    }
+}
+
+impl<P> Index<P> for ConcurrentNSW<P> {
+    fn size(&self) -> usize {
+        self.node_count()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        or_empty(self.try_search(query, k, ef))
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_skipping(query, k, ef, &|_| false)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let Some(&ep) = self.ep.get() else {
+            return Ok(vec![]);
+        };
+
+        let mut w = nsw::search_skipping(self, query, ef, ep, Point::distance, skip)?;
+        Ok(w.drain_asc().take(k).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_insert_and_search() {
+        let nsw = ConcurrentNSW::new(NSWOptions {
+            ef_construction: 16,
+            connections: 4,
+            max_connections: 8,
+            size: 1000,
+            ..NSWOptions::default()
+        });
+
+        // Searches run while the points are inserted
+        rayon::join(
+            || {
+                (0..1000)
+                    .into_par_iter()
+                    .try_for_each(|i| nsw.insert(i).map(|_| ()))
+                    .unwrap()
+            },
+            || {
+                for _ in 0..100 {
+                    let res = nsw.try_search(&500, 5, 16).unwrap();
+                    assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+                }
+            },
+        );
+
+        assert_eq!(nsw.size(), 1000);
+        assert!((0..1000).all(|v| !nsw.neighborhood(v).is_empty()));
+        assert!((0..1000).all(|v| nsw.neighborhood(v).len() <= 8));
+        for q in [0, 17, 500, 999] {
+            assert_eq!(*nsw.search(&q, 1, 32)[0].point, q);
+        }
+        assert!(matches!(
+            nsw.insert(1000),
+            Err(Error::OutOfRange {
+                index: 1000,
+                len: 1000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_capacity() {
+        let nsw = ConcurrentNSW::new(NSWOptions::default());
+        assert!(matches!(
+            nsw.insert(1),
+            Err(Error::InvalidParameter { name: "size", .. })
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_verify() {
+        let nsw = ConcurrentNSW::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            max_connections: 4,
+            size: 1000,
+            ..NSWOptions::default()
+        });
+
+        // Few connections per node, such that most inserts prune edges that others add
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let nsw = &nsw;
+                scope.spawn(move || {
+                    for i in (t..1000).step_by(8) {
+                        nsw.insert(i).unwrap();
+                    }
+                });
+            }
+        });
+
+        let violations = nsw.verify(Some(4));
+        assert!(
+            violations
+                .iter()
+                .all(|v| matches!(v, Violation::Unreachable { .. })),
+            "{violations:?}"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+};
+
+use crate::DynIndex;
+
+// Shared index that can be replaced while it is in use, e.g. by a rebuilt index in a long-running
+// server. Searches run on a snapshot taken with `load`, such that searches in flight during a
+// `swap` finish on the old index, which is dropped with its last snapshot.
+pub struct IndexHandle<P, I: ?Sized = dyn DynIndex<P>> {
+    current: RwLock<Arc<I>>,
+    _point: PhantomData<fn() -> P>,
+}
+
+impl<P, I: ?Sized + DynIndex<P>> IndexHandle<P, I> {
+    pub fn new(index: Arc<I>) -> Self {
+        Self {
+            current: RwLock::new(index),
+            _point: PhantomData,
+        }
+    }
+
+    // Index to run searches on, which stays alive until the snapshot is dropped
+    pub fn load(&self) -> Arc<I> {
+        // The lock only guards replacing the `Arc`, which can not leave it poisoned halfway
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    // Replace the index for all future searches, returning the old one
+    pub fn swap(&self, index: Arc<I>) -> Arc<I> {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *current, index)
+    }
+
+    pub fn size(&self) -> usize {
+        self.load().dyn_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bruteforce, IdxExt};
+
+    #[test]
+    fn test_swap() {
+        let handle: IndexHandle<i32> =
+            IndexHandle::new(Arc::new((0..10).collect::<Bruteforce<i32>>()));
+        assert_eq!(handle.size(), 10);
+
+        // A search in flight keeps using the index it started on
+        let old = handle.load();
+        let res = old.dyn_search(&4, 1, 1);
+
+        let previous = handle.swap(Arc::new((100..120).collect::<Bruteforce<i32>>()));
+        assert!(Arc::ptr_eq(&old, &previous));
+        assert_eq!(handle.size(), 20);
+
+        assert_eq!((res[0].key.into_usize(), *res[0].point), (4, 4));
+        assert_eq!(*handle.load().dyn_search(&4, 1, 1)[0].point, 100);
+
+        // The old index is dropped with its last snapshot
+        drop((res, previous));
+        assert_eq!(Arc::strong_count(&old), 1);
+    }
+}
@@ -1,18 +1,33 @@
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::{nsw, Distance, Graph, Idx, Index, IndexBuilder, NSWOptions, Point, SimpleGraph};
+use crate::{
+    nsw, or_empty,
+    repair::repair_layer,
+    verify::{verify_layer, verify_links},
+    BuildMonitor, CancellationToken, Distance, Error, Graph, Idx, Index, IndexBuilder, NSWOptions,
+    Point, ProgressFn, Result, SimpleGraph, Violation,
+};
 
+// The builder can be serialized mid-build, including the state of its random number generator, such
+// that a build resumed from it continues as if it had never been stopped
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct HNSWBuilder<P> {
     layers: Vec<SimpleGraph<(P, Idx)>>,
     base: SimpleGraph<P>,
     ep: Option<Idx>,
-    rng: StdRng,
+    // Same generator as `StdRng`, but one that can be serialized
+    rng: ChaCha12Rng,
     ef_construction: usize,
     connections: usize,
     max_connections: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    monitor: BuildMonitor,
 }
 
 impl<P> HNSWBuilder<P> {
@@ -21,7 +36,7 @@ impl<P> HNSWBuilder<P> {
             layers: Default::default(),
             base: Default::default(),
             ep: None,
-            rng: StdRng::seed_from_u64(
+            rng: ChaCha12Rng::seed_from_u64(
                 (rayon::current_num_threads()
                     ^ options.size
                     ^ options.ef_construction
@@ -31,31 +46,75 @@ impl<P> HNSWBuilder<P> {
             ef_construction: options.ef_construction,
             connections: options.connections,
             max_connections: options.max_connections,
+            monitor: BuildMonitor::new(&options),
         }
     }
 
+    // Number of points added so far
+    pub fn size(&self) -> usize {
+        self.base.size()
+    }
+
+    // Replace the progress callback, e.g. of a deserialized builder
+    pub fn set_progress(&mut self, progress: Option<ProgressFn>) {
+        self.monitor.progress = progress;
+    }
+
+    pub fn set_cancel(&mut self, cancel: Option<CancellationToken>) {
+        self.monitor.cancel = cancel;
+    }
+
+    fn report(&self, started: Instant) {
+        self.monitor
+            .report(self.base.size(), self.layers.len() + 1, started);
+    }
+
     fn random_level(&mut self) -> usize {
         let val: f32 = self.rng.gen();
         (-val.ln() * (1.0 / (self.connections as f32).ln())) as usize
     }
 }
 
+impl<P: Point + Clone> HNSWBuilder<P> {
+    fn try_add_monitored(&mut self, point: P, started: Instant) -> Result<()> {
+        self.monitor.check()?;
+        self.try_add(point)?;
+        self.report(started);
+        Ok(())
+    }
+}
+
 impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
     pub fn extend_parallel<T: IntoIterator<Item = P>>(&mut self, iter: T) {
+        match self.try_extend_parallel(iter) {
+            Ok(()) | Err(Error::Cancelled) => {}
+            Err(e) => panic!("could not extend graph: {e:?}"),
+        }
+    }
+
+    pub fn try_extend_parallel<T: IntoIterator<Item = P>>(&mut self, iter: T) -> Result<()> {
+        nsw::validate_options(self.ef_construction, self.connections)?;
+        let started = Instant::now();
         let mut iter = iter.into_iter();
 
         if self.ep.is_none() {
             if let Some(point) = iter.next() {
-                self.add(point);
+                self.try_add_monitored(point, started)?;
             }
         }
 
         // There needs to be some amount of nodes already to not generate a truly horrible graph.
-        self.extend(iter.by_ref().take(0.max(50_000 - self.base.size())));
+        for point in iter
+            .by_ref()
+            .take(50_000usize.saturating_sub(self.base.size()))
+        {
+            self.try_add_monitored(point, started)?;
+        }
 
         let chunk_size = rayon::current_num_threads() * 32;
 
         loop {
+            self.monitor.check()?;
             let chunk = iter.by_ref().take(chunk_size).collect::<Vec<_>>();
 
             if chunk.is_empty() {
@@ -99,13 +158,13 @@ impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
                     // Search until layer where we want to start inserting
                     for l in (level..self.layers.len()).rev() {
                         let layer = &self.layers[l];
-                        let w = nsw::search(layer, &point, 1, ep, |(p, _), q| p.distance(q));
+                        let w = nsw::search(layer, &point, 1, ep, |(p, _), q| p.distance(q))?;
                         ep = w.peek_min().unwrap().point.1;
                     }
 
-                    (point, idxs, ep)
+                    Ok((point, idxs, ep))
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>>>()?;
 
             // Insert in all layers below here
             for l in (0..level).rev() {
@@ -121,11 +180,11 @@ impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
                             self.ef_construction,
                             ep,
                             &|(p, _), (q, _)| p.distance(q),
-                        );
+                        )?;
 
-                        (neighbors, idxs)
+                        Ok((neighbors, idxs))
                     })
-                    .collect::<Vec<_>>();
+                    .collect::<Result<Vec<_>>>()?;
 
                 for (neighbors, idxs) in chunk_neighbors {
                     nsw::insert_neighbors(
@@ -134,7 +193,7 @@ impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
                         &neighbors,
                         self.max_connections,
                         |(p, _), (q, _)| p.distance(q),
-                    );
+                    )?;
                 }
             }
 
@@ -149,11 +208,11 @@ impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
                         self.ef_construction,
                         ep,
                         &Point::distance,
-                    );
+                    )?;
 
-                    (neighbors, idxs[0])
+                    Ok((neighbors, idxs[0]))
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>>>()?;
 
             // Insert in base layer
             for (neighbors, idx) in chunk_neighbors {
@@ -163,16 +222,24 @@ impl<P: Point + Clone + Send + Sync> HNSWBuilder<P> {
                     &neighbors,
                     self.max_connections,
                     Point::distance,
-                );
+                )?;
             }
+
+            self.report(started);
         }
+
+        Ok(())
     }
 }
 
 impl<P: Point + Clone> Extend<P> for HNSWBuilder<P> {
     fn extend<T: IntoIterator<Item = P>>(&mut self, iter: T) {
+        let started = Instant::now();
         for i in iter {
-            self.add(i);
+            match self.try_add_monitored(i, started) {
+                Err(Error::Cancelled) => return,
+                res => res.expect("could not add point"),
+            }
         }
     }
 }
@@ -181,6 +248,12 @@ impl<P: Point + Clone> IndexBuilder<P> for HNSWBuilder<P> {
     type Index = HNSW<P>;
 
     fn add(&mut self, point: P) {
+        self.try_add(point).expect("could not add point");
+    }
+
+    fn try_add(&mut self, point: P) -> Result<()> {
+        nsw::validate_options(self.ef_construction, self.connections)?;
+
         let base_idx = self.base.add(point.clone());
         let level = if self.ep.is_some() {
             self.random_level()
@@ -214,7 +287,7 @@ impl<P: Point + Clone> IndexBuilder<P> for HNSWBuilder<P> {
         // Search until layer where we want to start inserting
         for l in (level..self.layers.len()).rev() {
             let layer = &self.layers[l];
-            let w = nsw::search(layer, &point, 1, ep, |(p, _), q| p.distance(q));
+            let w = nsw::search(layer, &point, 1, ep, |(p, _), q| p.distance(q))?;
             ep = w.peek_min().unwrap().point.1;
         }
 
@@ -228,7 +301,7 @@ impl<P: Point + Clone> IndexBuilder<P> for HNSWBuilder<P> {
                 self.ef_construction,
                 ep,
                 |(p, _), (q, _)| p.distance(q),
-            );
+            )?;
         }
 
         // Insert in base layer
@@ -240,7 +313,9 @@ impl<P: Point + Clone> IndexBuilder<P> for HNSWBuilder<P> {
             self.ef_construction,
             ep,
             Point::distance,
-        );
+        )?;
+
+        Ok(())
     }
 
     fn build(self) -> Self::Index {
@@ -268,42 +343,183 @@ impl<P> HNSW<P> {
     pub fn base(&self) -> &SimpleGraph<P> {
         &self.base
     }
-}
 
-impl<P> Index<P> for HNSW<P> {
-    fn size(&self) -> usize {
-        self.base.size()
+    // Broken invariants of every layer, with degrees checked against `max_connections` if given.
+    // The entry point of each layer is found by following the links down from the top layer.
+    pub fn verify(&self, max_connections: Option<usize>) -> Vec<Violation> {
+        let mut violations = vec![];
+        let mut ep = self.ep;
+
+        for (l, layer) in self.layers.iter().enumerate().rev() {
+            let below = match l {
+                0 => self.base.size(),
+                _ => self.layers[l - 1].size(),
+            };
+            verify_layer(layer, l + 1, ep, max_connections, &mut violations);
+            verify_links(layer, l + 1, below, &mut violations);
+            ep = ep.and_then(|ep| layer.get(ep)).map(|&(_, link)| link);
+        }
+
+        verify_layer(&self.base, 0, ep, max_connections, &mut violations);
+        violations
     }
 
-    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    // Connect the nodes of every layer that are not reachable from the entry point of the layer,
+    // searching for them with `ef_construction` and connecting them within `max_connections` where
+    // possible. Returns the number of edges added.
+    pub fn repair(&mut self, options: &NSWOptions) -> Result<usize>
     where
         P: Point,
     {
-        let Some(mut ep) = self.ep else { return vec![] };
+        let mut added = 0;
+        let mut ep = self.ep;
+
+        for layer in self.layers.iter_mut().rev() {
+            let Some(layer_ep) = ep else {
+                break;
+            };
+            added += repair_layer(
+                layer,
+                layer_ep,
+                options.ef_construction,
+                options.max_connections,
+                |(p, _), (q, _)| p.distance(q),
+            )?;
+            ep = layer.get(layer_ep).map(|&(_, link)| link);
+        }
+
+        if let Some(ep) = ep {
+            added += repair_layer(
+                &mut self.base,
+                ep,
+                options.ef_construction,
+                options.max_connections,
+                Point::distance,
+            )?;
+        }
+
+        Ok(added)
+    }
+
+    // Insert points after the build, connecting them as the build would with the parameters of
+    // `options`. The points get the keys following those of the base layer, in order. Their layers
+    // are drawn by a generator seeded with the size of the graph rather than `options.size`.
+    pub fn insert(
+        &mut self,
+        points: impl IntoIterator<Item = P>,
+        options: &NSWOptions,
+    ) -> Result<()>
+    where
+        P: Point + Clone,
+    {
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            size: self.base.size(),
+            progress: options.progress.clone(),
+            cancel: options.cancel.clone(),
+            ..*options
+        });
+        builder.layers = std::mem::take(&mut self.layers);
+        builder.base = std::mem::take(&mut self.base);
+        builder.ep = self.ep.take();
+        let res = points
+            .into_iter()
+            .try_for_each(|point| builder.try_add(point));
+
+        // Points inserted before an error are kept
+        *self = builder.build();
+        res
+    }
+
+    // Search for a query of another type than the points, e.g. a query preprocessed for asymmetric
+    // distance, given the distance between a point and the query
+    pub fn search_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+    ) -> Vec<Distance<'a, P>> {
+        or_empty(self.try_search_by(query, k, ef, distance_fn))
+    }
+
+    pub fn try_search_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+    ) -> Result<Vec<Distance<'a, P>>> {
+        self.try_search_skipping_by(query, k, ef, distance_fn, |_| false)
+    }
+
+    // Skipped points are only left out of the base layer, as upper layers only lead to it
+    fn try_search_skipping_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+        skip: impl Fn(Idx) -> bool,
+    ) -> Result<Vec<Distance<'a, P>>> {
+        let Some(mut ep) = self.ep else {
+            return Ok(vec![]);
+        };
 
         // Search layers from top to bottom
         for layer in self.layers.iter().rev() {
-            let mut w = nsw::search(layer, query, 1, ep, |(p, _), q| p.distance(q));
+            let mut w = nsw::search(layer, query, 1, ep, |(p, _), q| distance_fn(p, q))?;
 
-            ep = w
-                .pop_min()
-                .expect("search must find something when graph is not empty")
-                .point
-                .1;
+            // The entry point is always found, so `w` is empty only if the layer is inconsistent
+            ep = w.pop_min().ok_or(Error::NodeNotFound(ep))?.point.1;
         }
 
         // Search base layer last
-        nsw::search(&self.base, query, ef, ep, Point::distance)
-            .drain_asc()
-            .take(k)
-            .collect()
+        Ok(
+            nsw::search_skipping(&self.base, query, ef, ep, distance_fn, skip)?
+                .drain_asc()
+                .take(k)
+                .collect(),
+        )
+    }
+}
+
+impl<P> Index<P> for HNSW<P> {
+    fn size(&self) -> usize {
+        self.base.size()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        self.search_by(query, k, ef, Point::distance)
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_by(query, k, ef, Point::distance)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_skipping_by(query, k, ef, Point::distance, skip)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_utils::unordered_eq;
+    use crate::{test_utils::unordered_eq, Progress};
     use min_max_heap::MinMaxHeap;
 
     #[test]
@@ -331,6 +547,95 @@ mod tests {
         assert_eq!(hnsw.size(), len);
     }
 
+    #[test]
+    fn test_progress_and_cancel() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let cancel = CancellationToken::new();
+        let inserted = Arc::new(AtomicUsize::new(0));
+        let progress: ProgressFn = {
+            let (cancel, inserted) = (cancel.clone(), inserted.clone());
+            Arc::new(move |progress: Progress| {
+                assert!(progress.layers >= 1);
+                inserted.store(progress.inserted, Ordering::Relaxed);
+                if progress.inserted == 50 {
+                    cancel.cancel();
+                }
+            })
+        };
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            size: 150,
+            progress: Some(progress),
+            cancel: Some(cancel),
+            ..NSWOptions::default()
+        });
+
+        // The build stops at the first check after it is cancelled
+        builder.extend(0..100);
+        assert_eq!((builder.size(), inserted.load(Ordering::Relaxed)), (50, 50));
+        assert_eq!(builder.try_extend_parallel(50..150), Err(Error::Cancelled));
+
+        builder.set_cancel(None);
+        builder.try_extend_parallel(50..150).unwrap();
+        assert_eq!(
+            (builder.size(), inserted.load(Ordering::Relaxed)),
+            (150, 150)
+        );
+        assert_eq!(*builder.build().search(&120, 1, 8)[0].point, 120);
+    }
+
+    #[test]
+    fn test_search_by() {
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            size: 20,
+            ..NSWOptions::default()
+        });
+
+        builder.extend(0..20);
+
+        let hnsw = builder.build();
+        let knns = hnsw
+            .search_by(&5.25, 3, 20, |&p, q: &f64| {
+                ((p as f64 - q).abs() * 4.0) as usize
+            })
+            .into_iter()
+            .map(|dist| (dist.key, dist.distance))
+            .collect::<Vec<_>>();
+        assert_eq!(knns, vec![(5, 1), (6, 3), (4, 5)]);
+    }
+
+    #[test]
+    fn test_insert() {
+        let options = NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            max_connections: 6,
+            ..NSWOptions::default()
+        };
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            size: 20,
+            progress: None,
+            cancel: None,
+            ..options
+        });
+        builder.extend(0..20);
+
+        let mut hnsw = builder.build();
+        hnsw.insert(20..40, &options).unwrap();
+
+        assert_eq!(hnsw.size(), 40);
+        assert!(hnsw.verify(Some(options.max_connections)).is_empty());
+        let res = hnsw.search(&30, 1, 8);
+        assert_eq!((res[0].key, *res[0].point), (30, 30));
+    }
+
     #[test]
     fn test_heuristic() {
         let k = 4;
@@ -0,0 +1,295 @@
+use rand::{rngs::StdRng, seq::index::sample, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Distance, Idx, IdxExt, Index, IndexBuilder, MinK, Point, Result};
+
+// Representative of a cluster of points, e.g. the mean for k-means or the bitwise majority for
+// k-majority on binary sketches
+pub trait Centroid: Sized {
+    fn centroid<'a>(points: impl IntoIterator<Item = &'a Self>) -> Option<Self>
+    where
+        Self: 'a;
+}
+
+pub struct IVFOptions {
+    pub nlist: usize,
+    pub iterations: usize,
+    pub training_size: usize,
+}
+
+impl Default for IVFOptions {
+    fn default() -> Self {
+        Self {
+            nlist: 1024,
+            iterations: 10,
+            training_size: 256 * 1024,
+        }
+    }
+}
+
+pub struct IVFBuilder<P> {
+    points: Vec<P>,
+    rng: StdRng,
+    nlist: usize,
+    iterations: usize,
+    training_size: usize,
+}
+
+impl<P> IVFBuilder<P> {
+    pub fn new(options: IVFOptions) -> Self {
+        Self {
+            points: vec![],
+            rng: StdRng::seed_from_u64(
+                (options.nlist ^ options.iterations ^ options.training_size) as u64,
+            ),
+            nlist: options.nlist,
+            iterations: options.iterations,
+            training_size: options.training_size,
+        }
+    }
+}
+
+fn nearest<P: Point>(centroids: &[P], point: &P) -> Idx {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(key, c)| Distance::new(c.distance(point), Idx::from_usize(key), c))
+        .min()
+        .expect("there must be at least one centroid")
+        .key
+}
+
+impl<P: Point + Centroid + Clone + Send + Sync> IVFBuilder<P> {
+    fn train(&mut self) -> Vec<P> {
+        let nlist = self.nlist.min(self.points.len());
+        let training = sample(
+            &mut self.rng,
+            self.points.len(),
+            self.training_size.clamp(nlist, self.points.len()),
+        )
+        .into_iter()
+        .map(|i| &self.points[i])
+        .collect::<Vec<_>>();
+
+        let mut centroids = training[..nlist]
+            .iter()
+            .map(|&p| p.clone())
+            .collect::<Vec<_>>();
+
+        for _ in 0..self.iterations {
+            let assignment = training
+                .par_iter()
+                .map(|p| nearest(&centroids, p))
+                .collect::<Vec<_>>();
+
+            let mut clusters = vec![vec![]; nlist];
+            for (&p, c) in training.iter().zip(assignment) {
+                clusters[c.into_usize()].push(p);
+            }
+
+            // Empty clusters keep their previous centroid
+            for (centroid, cluster) in centroids.iter_mut().zip(clusters) {
+                if let Some(c) = P::centroid(cluster) {
+                    *centroid = c;
+                }
+            }
+        }
+
+        centroids
+    }
+}
+
+impl<P: Point + Centroid + Clone + Send + Sync> IndexBuilder<P> for IVFBuilder<P> {
+    type Index = IVF<P>;
+
+    fn add(&mut self, point: P) {
+        self.points.push(point);
+    }
+
+    fn build(mut self) -> Self::Index {
+        if self.points.is_empty() {
+            return IVF {
+                centroids: vec![],
+                lists: vec![],
+                points: self.points,
+            };
+        }
+
+        let centroids = self.train();
+
+        let mut lists = vec![vec![]; centroids.len()];
+        let assignment = self
+            .points
+            .par_iter()
+            .map(|p| nearest(&centroids, p))
+            .collect::<Vec<_>>();
+        for (idx, c) in assignment.into_iter().enumerate() {
+            lists[c.into_usize()].push(Idx::from_usize(idx));
+        }
+
+        IVF {
+            centroids,
+            lists,
+            points: self.points,
+        }
+    }
+}
+
+impl<P: Point + Centroid + Clone + Send + Sync> Extend<P> for IVFBuilder<P> {
+    fn extend<T: IntoIterator<Item = P>>(&mut self, iter: T) {
+        for i in iter {
+            self.add(i);
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IVF<P> {
+    centroids: Vec<P>,
+    lists: Vec<Vec<Idx>>,
+    points: Vec<P>,
+}
+
+impl<P> IVF<P> {
+    pub fn centroids(&self) -> &Vec<P> {
+        &self.centroids
+    }
+
+    pub fn lists(&self) -> &Vec<Vec<Idx>> {
+        &self.lists
+    }
+
+    // Scan the `nprobe` lists nearest to `query`, leaving out the points for which `skip` is true
+    fn probe<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        nprobe: usize,
+        skip: impl Fn(Idx) -> bool,
+    ) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        let mut res = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(key, c)| Distance::new(c.distance(query), Idx::from_usize(key), c))
+            .min_k(nprobe.max(1))
+            .into_iter()
+            .flat_map(|c| self.lists[c.key.into_usize()].iter())
+            .filter(|&&key| !skip(key))
+            .map(|&key| {
+                let point = &self.points[key.into_usize()];
+                Distance::new(point.distance(query), key, point)
+            })
+            .min_k(k);
+
+        res.sort();
+        res
+    }
+}
+
+impl<P> Index<P> for IVF<P> {
+    fn size(&self) -> usize {
+        self.points.len()
+    }
+
+    // `ef` is the number of lists to probe (nprobe)
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        self.probe(query, k, ef, |_| false)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        Ok(self.probe(query, k, ef, skip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::unordered_eq;
+
+    impl Centroid for i32 {
+        fn centroid<'a>(points: impl IntoIterator<Item = &'a Self>) -> Option<Self> {
+            let (sum, count) = points
+                .into_iter()
+                .fold((0, 0), |(sum, count), p| (sum + p, count + 1));
+            (count > 0).then(|| sum / count)
+        }
+    }
+
+    #[test]
+    fn test_ivf() {
+        let k = 4;
+        let mut builder = IVFBuilder::new(IVFOptions {
+            nlist: 4,
+            ..IVFOptions::default()
+        });
+
+        builder.extend(0..100);
+
+        let ivf = builder.build();
+        assert_eq!(ivf.centroids().len(), 4);
+        assert_eq!(ivf.lists().iter().map(Vec::len).sum::<usize>(), 100);
+
+        let knns = ivf
+            .search(&50, k, ivf.centroids().len())
+            .into_iter()
+            .map(|dist| dist.point)
+            .copied();
+        assert!(unordered_eq(knns.clone(), 48..=51) || unordered_eq(knns, 49..=52));
+    }
+
+    #[test]
+    fn test_ivf_nprobe() {
+        let mut builder = IVFBuilder::new(IVFOptions {
+            nlist: 4,
+            ..IVFOptions::default()
+        });
+        builder.extend(0..100);
+        let ivf = builder.build();
+
+        // One probe scans only the list of the nearest centroid, as does an `ef` of 0
+        let list = &ivf.lists()[nearest(ivf.centroids(), &50).into_usize()];
+        let res = ivf.search(&50, 100, 1);
+        assert_eq!(res.len(), list.len());
+        assert!(res.iter().all(|d| list.contains(&d.key)));
+        assert_eq!(
+            ivf.search(&50, 100, 0)
+                .iter()
+                .map(|d| d.key)
+                .collect::<Vec<_>>(),
+            res.iter().map(|d| d.key).collect::<Vec<_>>()
+        );
+
+        // Every probe scans one more list, such that recall never drops as lists are added, and
+        // all lists find all neighbors
+        let exact = ivf.search(&50, 30, 4);
+        let recall = |nprobe| {
+            ivf.search(&50, 30, nprobe)
+                .iter()
+                .filter(|d| exact.contains(d))
+                .count()
+        };
+        let recalls = (1..=4).map(recall).collect::<Vec<_>>();
+        assert!(recalls.windows(2).all(|w| w[0] <= w[1]), "{recalls:?}");
+        assert!(recalls[0] < 30);
+        assert_eq!(recalls[3], 30);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{or_empty, Distance, Error, Idx, IdxExt, Index, Point, Result};
+
+// Index that points are added to and removed from after it is built. Added points are searched
+// exhaustively next to the index and get the keys following those of the index, and removed points
+// are skipped while the index is searched. Removed points stay in the index, as the keys of the
+// points after them must not change, so they still take memory and graph searches still traverse
+// them, which costs more the more points are removed, until the index is built again without them.
+// Every change counts towards the generation, such that a log of changes can tell which of them an
+// index already has.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LiveIndex<I, P> {
+    index: I,
+    added: Vec<P>,
+    removed: HashSet<Idx>,
+    generation: u64,
+}
+
+impl<I, P> LiveIndex<I, P> {
+    pub fn new(index: I) -> Self {
+        Self {
+            index,
+            added: vec![],
+            removed: HashSet::new(),
+            generation: 0,
+        }
+    }
+
+    // Index with the changes made to it before, e.g. as read from a file
+    pub fn with_changes(index: I, added: Vec<P>, removed: HashSet<Idx>, generation: u64) -> Self {
+        Self {
+            index,
+            added,
+            removed,
+            generation,
+        }
+    }
+
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    // Index to change in place, e.g. to repair, which must keep the keys of its points
+    pub fn index_mut(&mut self) -> &mut I {
+        &mut self.index
+    }
+
+    pub fn added(&self) -> &[P] {
+        &self.added
+    }
+
+    pub fn removed(&self) -> &HashSet<Idx> {
+        &self.removed
+    }
+
+    // Number of changes made to the index
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl<P, I: Index<P>> LiveIndex<I, P> {
+    // Number of keys given out, including those of removed points
+    pub fn keys(&self) -> usize {
+        self.index.size() + self.added.len()
+    }
+
+    pub fn add(&mut self, point: P) -> Idx {
+        let key = Idx::from_usize(self.keys());
+        self.added.push(point);
+        self.generation += 1;
+        key
+    }
+
+    // Move the added points into the index with `insert`, which inserts the first of the points it
+    // is given and returns the others. Keys do not change, as the index gives the inserted points
+    // the keys following its own. Removed points stay in the index and are still skipped.
+    pub fn compact<E>(
+        &mut self,
+        insert: impl FnOnce(&mut I, Vec<P>) -> std::result::Result<Vec<P>, E>,
+    ) -> std::result::Result<(), E> {
+        let added = std::mem::take(&mut self.added);
+        let size = self.index.size();
+        let count = added.len();
+
+        self.added = insert(&mut self.index, added)?;
+        debug_assert_eq!(self.index.size() + self.added.len(), size + count);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: Idx) -> Result<()> {
+        if key.into_usize() >= self.keys() {
+            return Err(Error::OutOfRange {
+                index: key.into_usize(),
+                len: self.keys(),
+            });
+        }
+        if !self.removed.insert(key) {
+            return Err(Error::NodeNotFound(key));
+        }
+
+        self.generation += 1;
+        Ok(())
+    }
+
+    // Candidates from the index merged with the added points that are not skipped
+    fn merge<'a>(
+        &'a self,
+        candidates: Vec<Distance<'a, P>>,
+        query: &P,
+        k: usize,
+        skip: impl Fn(Idx) -> bool,
+    ) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        let offset = self.index.size();
+        let added = self
+            .added
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Distance::new(query.distance(p), Idx::from_usize(offset + i), p));
+
+        let mut res = candidates
+            .into_iter()
+            .chain(added.filter(|d| !skip(d.key)))
+            .collect::<Vec<_>>();
+
+        res.sort();
+        res.dedup();
+        res.truncate(k);
+        res
+    }
+}
+
+impl<P, I: Index<P>> Index<P> for LiveIndex<I, P> {
+    fn size(&self) -> usize {
+        self.keys() - self.removed.len()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        or_empty(self.try_search(query, k, ef))
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_skipping(query, k, ef, &|_| false)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let removed = &self.removed;
+        let skip = |key| removed.contains(&key) || skip(key);
+
+        let candidates = self.index.try_search_skipping(query, k, ef, &skip)?;
+        Ok(self.merge(candidates, query, k, skip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bruteforce, IndexBuilder, NSWBuilder, NSWOptions};
+
+    #[test]
+    fn test_live() {
+        let mut live = LiveIndex::new((0..10).map(|p| p * 10).collect::<Bruteforce<i32>>());
+
+        assert_eq!(live.add(42), 10);
+        assert_eq!(live.add(47), 11);
+        live.remove(4).unwrap();
+        assert_eq!((live.size(), live.keys(), live.generation()), (11, 12, 3));
+
+        let res = live.search(&41, 3, 10);
+        assert_eq!(
+            res.iter().map(|d| (d.key, *d.point)).collect::<Vec<_>>(),
+            vec![(10, 42), (11, 47), (5, 50)]
+        );
+
+        assert!(matches!(live.remove(4), Err(Error::NodeNotFound(4))));
+        assert!(matches!(
+            live.remove(12),
+            Err(Error::OutOfRange { index: 12, len: 12 })
+        ));
+        assert_eq!(live.generation(), 3);
+    }
+
+    #[test]
+    fn test_live_removed_beyond_ef() {
+        let mut live = LiveIndex::new((0..10).collect::<Bruteforce<i32>>());
+        for key in 0..5 {
+            live.remove(key).unwrap();
+        }
+
+        let res = live.search(&0, 2, 2);
+        assert_eq!(res.iter().map(|d| d.key).collect::<Vec<_>>(), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_live_skips_removed_in_graph() {
+        let mut builder = NSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            size: 100,
+            ..NSWOptions::default()
+        });
+        builder.extend(0..100);
+        let mut live = LiveIndex::new(builder.build());
+        for key in 30..70 {
+            live.remove(key).unwrap();
+        }
+
+        // Removed points are traversed but do not take the place of others among the `ef` found
+        let res = live.search(&50, 2, 2);
+        assert_eq!(res.iter().map(|d| d.key).collect::<Vec<_>>(), vec![70, 29]);
+        assert!(live
+            .search(&50, 10, 10)
+            .iter()
+            .all(|d| !live.removed().contains(&d.key)));
+    }
+
+    #[test]
+    fn test_live_compact() {
+        let mut live = LiveIndex::new((0..10).map(|p| p * 10).collect::<Bruteforce<i32>>());
+        live.add(42);
+        live.add(47);
+        live.remove(11).unwrap();
+
+        // Only the first added point is inserted
+        live.compact(|index, mut added| {
+            index.extend(added.drain(..1));
+            Ok::<_, Error>(added)
+        })
+        .unwrap();
+        assert_eq!(
+            (live.index().size(), live.added(), live.size()),
+            (11, &[47][..], 11)
+        );
+
+        let res = live.search(&44, 3, 10);
+        assert_eq!(
+            res.iter().map(|d| (d.key, *d.point)).collect::<Vec<_>>(),
+            vec![(10, 42), (4, 40), (5, 50)]
+        );
+    }
+}
@@ -1,21 +1,46 @@
 pub mod bruteforce;
+pub mod concurrent;
+pub mod handle;
 pub mod hnsw;
+pub mod ivf;
+pub mod live;
 pub mod nsw;
+pub mod progress;
+pub mod repair;
+pub mod rerank;
+pub mod sharded;
+pub mod verify;
 use std::cmp::Ordering;
 
 pub use bruteforce::*;
+pub use concurrent::*;
+pub use handle::*;
 pub use hnsw::*;
+pub use ivf::*;
+pub use live::*;
 pub use nsw::*;
+pub use progress::*;
 use rayon::iter::{IntoParallelIterator, ParallelIterator as _};
+pub use rerank::*;
+pub use sharded::*;
+pub use verify::*;
+
+use crate::{Idx, Result};
 
 #[cfg(feature = "tracing")]
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
 pub trait IndexBuilder<P> {
     type Index: Index<P>;
 
     fn add(&mut self, point: P);
     fn build(self) -> Self::Index;
+
+    // Like `add`, but returns an error instead of panicking when the point can not be added
+    fn try_add(&mut self, point: P) -> Result<()> {
+        self.add(point);
+        Ok(())
+    }
 }
 
 pub trait Index<P> {
@@ -24,6 +49,47 @@ pub trait Index<P> {
     where
         P: Point;
 
+    // Like `search`, but returns an error instead of panicking on invalid parameters or an
+    // inconsistent index
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        Ok(self.search(query, k, ef))
+    }
+
+    // Like `try_search`, but without the points for which `skip` is true. Graph indexes traverse
+    // skipped points without counting them among the `ef` candidates, and other indexes leave them
+    // out as they scan, such that skipped points do not take the place of others. By default,
+    // twice as many candidates are fetched until `k` are left or the index has no more.
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let mut fetch = k;
+        loop {
+            let candidates = self.try_search(query, fetch, ef.max(fetch))?;
+            let exhausted = candidates.len() < fetch || fetch >= self.size();
+
+            let mut res = candidates
+                .into_iter()
+                .filter(|d| !skip(d.key))
+                .collect::<Vec<_>>();
+            if res.len() >= k || exhausted {
+                res.truncate(k);
+                return Ok(res);
+            }
+
+            fetch *= 2;
+        }
+    }
+
     #[cfg_attr(feature = "tracing", instrument(skip(self, queries)))]
     fn knns<I>(&self, queries: I, k: usize, ef: usize) -> Vec<Vec<Distance<'_, P>>>
     where
@@ -42,14 +108,128 @@ pub trait Index<P> {
     }
 }
 
+// Object-safe version of `Index`, implemented for every index, such that indexes of different kinds
+// can be chosen at runtime as `Box<dyn DynIndex<P>>`, which is an `Index` itself. Methods are
+// prefixed to not be ambiguous with those of `Index`.
+pub trait DynIndex<P>: Send + Sync {
+    fn dyn_size(&self) -> usize;
+
+    fn dyn_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>;
+
+    fn dyn_try_search<'a>(&'a self, query: &P, k: usize, ef: usize)
+        -> Result<Vec<Distance<'a, P>>>;
+
+    fn dyn_try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>;
+
+    fn dyn_knns(&self, queries: Vec<P>, k: usize, ef: usize) -> Vec<Vec<Distance<'_, P>>>;
+}
+
+impl<P: Point + Sync, I: Index<P> + Send + Sync> DynIndex<P> for I {
+    fn dyn_size(&self) -> usize {
+        self.size()
+    }
+
+    fn dyn_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>> {
+        self.search(query, k, ef)
+    }
+
+    fn dyn_try_search<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<Distance<'a, P>>> {
+        self.try_search(query, k, ef)
+    }
+
+    fn dyn_try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>> {
+        self.try_search_skipping(query, k, ef, skip)
+    }
+
+    fn dyn_knns(&self, queries: Vec<P>, k: usize, ef: usize) -> Vec<Vec<Distance<'_, P>>> {
+        self.knns(queries, k, ef)
+    }
+}
+
+// Dispatches to the boxed index, as the box is also a `DynIndex` itself
+impl<P: Point + Sync> Index<P> for Box<dyn DynIndex<P> + '_> {
+    fn size(&self) -> usize {
+        (**self).dyn_size()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        (**self).dyn_search(query, k, ef)
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        (**self).dyn_try_search(query, k, ef)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        (**self).dyn_try_search_skipping(query, k, ef, skip)
+    }
+
+    fn knns<I>(&self, queries: I, k: usize, ef: usize) -> Vec<Vec<Distance<'_, P>>>
+    where
+        Self: Sync,
+        I: IntoIterator<Item = P>,
+        P: Point + Sync,
+    {
+        (**self).dyn_knns(queries.into_iter().collect(), k, ef)
+    }
+}
+
+// Results of a search by a method that can not return an error, which logs it and returns no
+// results instead of panicking
+pub(crate) fn or_empty<P>(res: Result<Vec<Distance<'_, P>>>) -> Vec<Distance<'_, P>> {
+    res.unwrap_or_else(|_e| {
+        #[cfg(feature = "tracing")]
+        warn!(error = %_e, "Search failed, returning no results");
+        vec![]
+    })
+}
+
 pub trait Point {
     fn distance(&self, other: &Self) -> usize;
+
+    // Distance that may stop early once it exceeds `bound`, in which case any value greater than
+    // `bound` can be returned
+    fn distance_bounded(&self, other: &Self, _bound: usize) -> usize {
+        self.distance(other)
+    }
 }
 
 #[derive(Debug)]
 pub struct Distance<'a, P> {
     pub distance: usize,
-    pub key: usize,
+    pub key: Idx,
     pub point: &'a P,
 }
 
@@ -64,7 +244,7 @@ impl<'a, P> Clone for Distance<'a, P> {
 }
 
 impl<'a, P> Distance<'a, P> {
-    pub const fn new(distance: usize, key: usize, point: &'a P) -> Self {
+    pub const fn new(distance: usize, key: Idx, point: &'a P) -> Self {
         Self {
             distance,
             key,
@@ -95,3 +275,44 @@ impl<'a, P> Ord for Distance<'a, P> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dyn_index() {
+        let mut hnsw = HNSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            size: 50,
+            ..NSWOptions::default()
+        });
+        hnsw.extend(50..100);
+
+        // Shards of different kinds
+        let sharded = [
+            (
+                0,
+                Box::new((0..50).collect::<Bruteforce<_>>()) as Box<dyn DynIndex<i32>>,
+            ),
+            (50, Box::new(hnsw.build())),
+        ]
+        .into_iter()
+        .collect::<ShardedIndex<_>>();
+
+        assert_eq!(sharded.size(), 100);
+
+        let res = sharded.search(&49, 3, 16);
+        assert_eq!(
+            res.iter().map(|d| *d.point).collect::<Vec<_>>(),
+            vec![49, 48, 50]
+        );
+
+        let index: Box<dyn DynIndex<i32>> = Box::new(sharded);
+        let res = index.knns([10, 90], 1, 16);
+        assert_eq!(res[0][0].key, 10);
+        assert_eq!(res[1][0].key, 90);
+        assert!(index.try_search(&10, 1, 0).is_err());
+    }
+}
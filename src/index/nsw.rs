@@ -1,6 +1,10 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Instant};
 
-use crate::{Distance, Graph, Idx, Index, IndexBuilder, Point, SimpleGraph};
+use crate::{
+    or_empty, repair::repair_layer, verify::verify_layer, BuildMonitor, CancellationToken,
+    Distance, Error, Graph, Idx, Index, IndexBuilder, Point, ProgressFn, Result, SearchGraph,
+    SimpleGraph, Violation,
+};
 use min_max_heap::MinMaxHeap;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 #[cfg(feature = "serde")]
@@ -42,145 +46,189 @@ pub(crate) fn select_neighbors<'a, P>(
 //}
 
 pub(crate) fn search_select_neighbors<P>(
-    graph: &impl Graph<P>,
+    graph: &impl SearchGraph<P>,
     point: &P,
     m: usize,
     ef: usize,
     ep: Idx,
     distance_fn: &impl Fn(&P, &P) -> usize,
-) -> Vec<Idx> {
-    let w = search(graph, point, ef, ep, distance_fn);
+) -> Result<Vec<Idx>> {
+    let w = search(graph, point, ef, ep, distance_fn)?;
 
-    select_neighbors(w, m, distance_fn)
+    Ok(select_neighbors(w, m, distance_fn)
         .into_iter()
         .map(|x| x.key)
-        .collect()
+        .collect())
 }
 
 pub(crate) fn insert_point<P: Point>(
-    graph: &mut impl Graph<P>,
+    graph: &mut (impl Graph<P> + SearchGraph<P>),
     point: P,
     m: usize,
     m_max: usize,
     ef: usize,
     ep: Idx,
-) -> Idx {
+) -> Result<Idx> {
     let point_idx = graph.add(point);
 
     insert_idx(graph, point_idx, m, m_max, ef, ep, Point::distance)
 }
 
 pub(crate) fn insert_idx<P>(
-    graph: &mut impl Graph<P>,
+    graph: &mut (impl Graph<P> + SearchGraph<P>),
     point_idx: Idx,
     m: usize,
     m_max: usize,
     ef: usize,
     ep: Idx,
     distance_fn: impl Fn(&P, &P) -> usize,
-) -> Idx {
-    let point = graph
-        .get(point_idx)
-        .expect("insert_idx expects point_idx to be in the graph");
-    let neighbors = search_select_neighbors(graph, point, m, ef, ep, &distance_fn);
+) -> Result<Idx> {
+    let point = graph.get(point_idx).ok_or(Error::NodeNotFound(point_idx))?;
+    let neighbors = search_select_neighbors(graph, point, m, ef, ep, &distance_fn)?;
 
-    insert_neighbors(graph, point_idx, &neighbors, m_max, distance_fn);
+    insert_neighbors(graph, point_idx, &neighbors, m_max, distance_fn)?;
 
-    *neighbors
-        .first()
-        .expect("there should at least be the element we inserted")
+    // There is at least the element we inserted, unless no neighbors are selected
+    neighbors.first().copied().ok_or(Error::InvalidParameter {
+        name: "connections",
+        reason: "must be positive",
+    })
 }
 
 pub(crate) fn insert_neighbors<P>(
     graph: &mut impl Graph<P>,
     point_idx: Idx,
-    neighbors: &Vec<Idx>,
+    neighbors: &[Idx],
     m_max: usize,
     distance_fn: impl Fn(&P, &P) -> usize,
-) {
-    for e in neighbors {
-        graph.add_edge(point_idx, *e);
+) -> Result<()> {
+    // The first point of a graph is inserted from itself and finds itself as its neighbor
+    let neighbors = neighbors.iter().copied().filter(|&e| e != point_idx);
+
+    for e in neighbors.clone() {
+        graph.add_edge(point_idx, e);
     }
 
-    for &e in neighbors {
-        let e_elem = graph.get(e).unwrap();
+    for e in neighbors {
+        let e_elem = graph.get(e).ok_or(Error::NodeNotFound(e))?;
         let e_conn = graph.neighborhood(e).copied().collect::<Vec<_>>();
 
         if e_conn.len() <= m_max {
             continue;
         }
 
-        let candidates = e_conn
-            .into_iter()
-            .map(|idx| {
-                let v = graph.get(idx).unwrap();
-                Distance::new(distance_fn(v, e_elem), idx, v)
-            })
-            .collect::<MinMaxHeap<_>>();
-
-        let e_new_conn = select_neighbors(candidates, m_max, &distance_fn);
-
-        let keys = e_new_conn
-            .into_iter()
-            .map(|dist| dist.key)
-            .collect::<Vec<_>>();
+        let keys = prune_neighbors(e_elem, e_conn, |idx| graph.get(idx), m_max, &distance_fn)?;
         graph.clear_edges(e);
         graph.add_neighbors(e, keys.into_iter());
         graph.add_edge(point_idx, e); // TODO: Needed?
     }
+
+    Ok(())
+}
+
+// Connections of a node `e_elem` with more than `m_max` connections that are kept
+pub(crate) fn prune_neighbors<'a, P: 'a>(
+    e_elem: &P,
+    e_conn: Vec<Idx>,
+    get: impl Fn(Idx) -> Option<&'a P>,
+    m_max: usize,
+    distance_fn: impl Fn(&P, &P) -> usize,
+) -> Result<Vec<Idx>> {
+    let candidates = e_conn
+        .into_iter()
+        .map(|idx| {
+            let v = get(idx).ok_or(Error::NodeNotFound(idx))?;
+            Ok(Distance::new(distance_fn(v, e_elem), idx, v))
+        })
+        .collect::<Result<MinMaxHeap<_>>>()?;
+
+    Ok(select_neighbors(candidates, m_max, &distance_fn)
+        .into_iter()
+        .map(|dist| dist.key)
+        .collect())
 }
 
 pub(crate) fn search<'a, P, Q>(
-    graph: &'a impl Graph<P>,
+    graph: &'a impl SearchGraph<P>,
     query: &Q,
     ef: usize,
     ep: Idx,
     distance_fn: impl Fn(&P, &Q) -> usize,
-) -> MinMaxHeap<Distance<'a, P>> {
-    let ep_elem = graph.get(ep).expect("entry point was not in graph");
+) -> Result<MinMaxHeap<Distance<'a, P>>> {
+    search_skipping(graph, query, ef, ep, distance_fn, |_| false)
+}
+
+// Like `search`, but the nodes for which `skip` is true are only traversed and never among the
+// results, such that the search goes on until `ef` other nodes are found
+pub(crate) fn search_skipping<'a, P, Q>(
+    graph: &'a impl SearchGraph<P>,
+    query: &Q,
+    ef: usize,
+    ep: Idx,
+    distance_fn: impl Fn(&P, &Q) -> usize,
+    skip: impl Fn(Idx) -> bool,
+) -> Result<MinMaxHeap<Distance<'a, P>>> {
+    if ef == 0 {
+        return Err(Error::InvalidParameter {
+            name: "ef",
+            reason: "must be positive",
+        });
+    }
+
+    let ep_elem = graph.node(ep).ok_or(Error::NodeNotFound(ep))?;
     let dist = Distance::new(distance_fn(ep_elem, query), ep, ep_elem);
 
     let mut visited = HashSet::with_capacity(2048);
     visited.insert(ep);
-    let mut w = MinMaxHeap::from_iter([dist.clone()]);
+    let mut w = MinMaxHeap::new();
+    if !skip(ep) {
+        w.push(dist.clone());
+    }
     let mut cands = MinMaxHeap::from_iter([dist]);
+    // Farthest of the `ef` nearest nodes found, once that many are found
+    let bound = |w: &MinMaxHeap<Distance<'a, P>>| {
+        w.peek_max().filter(|_| w.len() >= ef).map(|f| f.distance)
+    };
 
-    while !cands.is_empty() {
-        let c = cands.pop_min().expect("cands can't be empty");
-        let f = w.peek_max().expect("w can't be empty");
-
-        if c.distance > f.distance {
+    while let Some(c) = cands.pop_min() {
+        if bound(&w).is_some_and(|f| c.distance > f) {
             break;
         }
 
-        for e in graph.neighborhood(c.key) {
-            if visited.contains(e) {
-                continue;
+        graph.visit_neighborhood(c.key, |e| {
+            if visited.contains(&e) {
+                return Ok(());
             }
 
-            visited.insert(*e);
-            let f = w.peek_max().expect("w can't be empty");
+            visited.insert(e);
 
-            let point = graph.get(*e).unwrap();
-            let e_dist = Distance::new(distance_fn(point, query), *e, point);
+            let point = graph.node(e).ok_or(Error::NodeNotFound(e))?;
+            let e_dist = Distance::new(distance_fn(point, query), e, point);
 
-            if e_dist.distance >= f.distance && w.len() >= ef {
-                continue;
+            if bound(&w).is_some_and(|f| e_dist.distance >= f) {
+                return Ok(());
             }
 
-            cands.push(e_dist.clone());
-            w.push(e_dist);
-
-            if w.len() > ef {
-                w.pop_max();
+            if !skip(e) {
+                w.push(e_dist.clone());
+                if w.len() > ef {
+                    w.pop_max();
+                }
             }
-        }
+            cands.push(e_dist);
+
+            Ok(())
+        })?;
     }
 
     #[cfg(feature = "tracing")]
-    trace!(visited = visited.len(), size = graph.size(), "visited");
+    trace!(
+        visited = visited.len(),
+        size = graph.node_count(),
+        "visited"
+    );
 
-    w
+    Ok(w)
 }
 
 pub struct NSWOptions {
@@ -188,6 +236,10 @@ pub struct NSWOptions {
     pub connections: usize,
     pub max_connections: usize,
     pub size: usize,
+    // Called as points are inserted by `extend` and `extend_parallel`
+    pub progress: Option<ProgressFn>,
+    // Checked before points are inserted by `extend` and `extend_parallel`
+    pub cancel: Option<CancellationToken>,
 }
 
 impl Default for NSWOptions {
@@ -197,16 +249,21 @@ impl Default for NSWOptions {
             connections: 16,
             max_connections: 32,
             size: 0,
+            progress: None,
+            cancel: None,
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NSWBuilder<P> {
     graph: SimpleGraph<P>,
     ep: Option<Idx>,
     ef_construction: usize,
     connections: usize,
     max_connections: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    monitor: BuildMonitor,
 }
 
 impl<P> NSWBuilder<P> {
@@ -217,26 +274,65 @@ impl<P> NSWBuilder<P> {
             ef_construction: options.ef_construction,
             connections: options.connections,
             max_connections: options.max_connections,
+            monitor: BuildMonitor::new(&options),
         }
     }
+
+    // Number of points added so far
+    pub fn size(&self) -> usize {
+        self.graph.size()
+    }
+
+    // Replace the progress callback, e.g. of a deserialized builder
+    pub fn set_progress(&mut self, progress: Option<ProgressFn>) {
+        self.monitor.progress = progress;
+    }
+
+    pub fn set_cancel(&mut self, cancel: Option<CancellationToken>) {
+        self.monitor.cancel = cancel;
+    }
+}
+
+impl<P: Point> NSWBuilder<P> {
+    fn try_add_monitored(&mut self, point: P, started: Instant) -> Result<()> {
+        self.monitor.check()?;
+        self.try_add(point)?;
+        self.monitor.report(self.graph.size(), 1, started);
+        Ok(())
+    }
 }
 
 impl<P: Point + Send + Sync> NSWBuilder<P> {
     pub fn extend_parallel<T: IntoIterator<Item = P>>(&mut self, iter: T) {
+        match self.try_extend_parallel(iter) {
+            Ok(()) | Err(Error::Cancelled) => {}
+            Err(e) => panic!("could not extend graph: {e:?}"),
+        }
+    }
+
+    pub fn try_extend_parallel<T: IntoIterator<Item = P>>(&mut self, iter: T) -> Result<()> {
+        validate_options(self.ef_construction, self.connections)?;
+        let started = Instant::now();
         let mut iter = iter.into_iter();
 
         if self.ep.is_none() {
             if let Some(point) = iter.next() {
-                self.add(point);
+                self.try_add_monitored(point, started)?;
             }
         }
 
         // There needs to be some amount of nodes already to not generate a truly horrible graph.
-        self.extend(iter.by_ref().take(0.max(50_000 - self.graph.size())));
+        for point in iter
+            .by_ref()
+            .take(50_000usize.saturating_sub(self.graph.size()))
+        {
+            self.try_add_monitored(point, started)?;
+        }
 
         let chunk_size = rayon::current_num_threads() * 32;
 
         loop {
+            self.monitor.check()?;
             let chunk = iter.by_ref().take(chunk_size).collect::<Vec<_>>();
 
             if chunk.is_empty() {
@@ -249,7 +345,10 @@ impl<P: Point + Send + Sync> NSWBuilder<P> {
                 .collect::<Vec<_>>()
                 .into_par_iter()
                 .map(|point_idx| {
-                    let point = self.graph.get(point_idx).unwrap();
+                    let point = self
+                        .graph
+                        .get(point_idx)
+                        .ok_or(Error::NodeNotFound(point_idx))?;
 
                     let neighbors = search_select_neighbors(
                         &self.graph,
@@ -258,11 +357,11 @@ impl<P: Point + Send + Sync> NSWBuilder<P> {
                         self.ef_construction,
                         self.ep.unwrap(),
                         &Point::distance,
-                    );
+                    )?;
 
-                    (point_idx, neighbors)
+                    Ok((point_idx, neighbors))
                 })
-                .collect::<Vec<_>>()
+                .collect::<Result<Vec<_>>>()?
             {
                 insert_neighbors(
                     &mut self.graph,
@@ -270,16 +369,42 @@ impl<P: Point + Send + Sync> NSWBuilder<P> {
                     &neighbors,
                     self.max_connections,
                     Point::distance,
-                );
+                )?;
             }
+
+            self.monitor.report(self.graph.size(), 1, started);
         }
+
+        Ok(())
     }
 }
 
+// Options that would make insertion fail
+pub(crate) fn validate_options(ef_construction: usize, connections: usize) -> Result<()> {
+    if ef_construction == 0 {
+        return Err(Error::InvalidParameter {
+            name: "ef_construction",
+            reason: "must be positive",
+        });
+    }
+    if connections == 0 {
+        return Err(Error::InvalidParameter {
+            name: "connections",
+            reason: "must be positive",
+        });
+    }
+
+    Ok(())
+}
+
 impl<P: Point> Extend<P> for NSWBuilder<P> {
     fn extend<T: IntoIterator<Item = P>>(&mut self, iter: T) {
+        let started = Instant::now();
         for i in iter {
-            self.add(i);
+            match self.try_add_monitored(i, started) {
+                Err(Error::Cancelled) => return,
+                res => res.expect("could not add point"),
+            }
         }
     }
 }
@@ -288,6 +413,12 @@ impl<P: Point> IndexBuilder<P> for NSWBuilder<P> {
     type Index = NSW<P>;
 
     fn add(&mut self, point: P) {
+        self.try_add(point).expect("could not add point");
+    }
+
+    fn try_add(&mut self, point: P) -> Result<()> {
+        validate_options(self.ef_construction, self.connections)?;
+
         match self.ep {
             Some(ep) => insert_point(
                 &mut self.graph,
@@ -310,7 +441,9 @@ impl<P: Point> IndexBuilder<P> for NSWBuilder<P> {
                     Point::distance,
                 )
             }
-        };
+        }?;
+
+        Ok(())
     }
 
     fn build(self) -> Self::Index {
@@ -331,6 +464,102 @@ impl<P> NSW<P> {
     pub fn graph(&self) -> &SimpleGraph<P> {
         &self.graph
     }
+
+    // Broken invariants of the graph, with degrees checked against `max_connections` if given
+    pub fn verify(&self, max_connections: Option<usize>) -> Vec<Violation> {
+        let mut violations = vec![];
+        verify_layer(&self.graph, 0, self.ep, max_connections, &mut violations);
+        violations
+    }
+
+    // Connect the nodes that are not reachable from the entry point, searching for them with
+    // `ef_construction` and connecting them within `max_connections` where possible. Returns the
+    // number of edges added.
+    pub fn repair(&mut self, options: &NSWOptions) -> Result<usize>
+    where
+        P: Point,
+    {
+        let Some(ep) = self.ep else {
+            return Ok(0);
+        };
+
+        repair_layer(
+            &mut self.graph,
+            ep,
+            options.ef_construction,
+            options.max_connections,
+            Point::distance,
+        )
+    }
+
+    // Insert points after the build, connecting them as the build would with the parameters of
+    // `options`. The points get the keys following those of the graph, in order.
+    pub fn insert(
+        &mut self,
+        points: impl IntoIterator<Item = P>,
+        options: &NSWOptions,
+    ) -> Result<()>
+    where
+        P: Point,
+    {
+        let mut builder = NSWBuilder {
+            graph: std::mem::take(&mut self.graph),
+            ep: self.ep.take(),
+            ef_construction: options.ef_construction,
+            connections: options.connections,
+            max_connections: options.max_connections,
+            monitor: BuildMonitor::new(options),
+        };
+        let res = points
+            .into_iter()
+            .try_for_each(|point| builder.try_add(point));
+
+        // Points inserted before an error are kept
+        *self = builder.build();
+        res
+    }
+
+    // Search for a query of another type than the points, given the distance between a point and
+    // the query
+    pub fn search_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+    ) -> Vec<Distance<'a, P>> {
+        or_empty(self.try_search_by(query, k, ef, distance_fn))
+    }
+
+    pub fn try_search_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+    ) -> Result<Vec<Distance<'a, P>>> {
+        self.try_search_skipping_by(query, k, ef, distance_fn, |_| false)
+    }
+
+    fn try_search_skipping_by<'a, Q>(
+        &'a self,
+        query: &Q,
+        k: usize,
+        ef: usize,
+        distance_fn: impl Fn(&P, &Q) -> usize,
+        skip: impl Fn(Idx) -> bool,
+    ) -> Result<Vec<Distance<'a, P>>> {
+        let Some(ep) = self.ep else {
+            return Ok(vec![]);
+        };
+
+        Ok(
+            search_skipping(&self.graph, query, ef, ep, distance_fn, skip)?
+                .drain_asc()
+                .take(k)
+                .collect(),
+        )
+    }
 }
 
 impl<P> Index<P> for NSW<P> {
@@ -342,12 +571,27 @@ impl<P> Index<P> for NSW<P> {
     where
         P: Point,
     {
-        self.ep.map_or_else(Vec::default, |ep| {
-            search(&self.graph, query, ef, ep, Point::distance)
-                .drain_asc()
-                .take(k)
-                .collect()
-        })
+        self.search_by(query, k, ef, Point::distance)
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_by(query, k, ef, Point::distance)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        self.try_search_skipping_by(query, k, ef, Point::distance, skip)
     }
 }
 
@@ -384,6 +628,32 @@ mod tests {
         assert!(unordered_eq(knns, 3..=6));
     }
 
+    #[test]
+    fn test_errors() {
+        let mut builder = NSWBuilder::new(NSWOptions {
+            connections: 0,
+            ..NSWOptions::default()
+        });
+        assert!(matches!(
+            builder.try_add(1),
+            Err(Error::InvalidParameter {
+                name: "connections",
+                ..
+            })
+        ));
+
+        let mut builder = NSWBuilder::new(NSWOptions::default());
+        builder.try_add(1).unwrap();
+        builder.try_add(2).unwrap();
+
+        let nsw = builder.build();
+        assert_eq!(nsw.try_search(&1, 1, 1).unwrap().len(), 1);
+        assert!(matches!(
+            nsw.try_search(&1, 1, 0),
+            Err(Error::InvalidParameter { name: "ef", .. })
+        ));
+    }
+
     #[test]
     fn test_heuristic() {
         let k = 4;
@@ -0,0 +1,76 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Error, NSWOptions, Result};
+
+// Progress of a build, reported to `NSWOptions::progress` as points are inserted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    // Points in the graph, including those inserted by earlier calls
+    pub inserted: usize,
+    // Layers of the graph including the base layer, always 1 for NSW
+    pub layers: usize,
+    // Time since the call inserting the points started
+    pub elapsed: Duration,
+}
+
+pub type ProgressFn = Arc<dyn Fn(Progress) + Send + Sync>;
+
+// Stops a build from another thread. A cancelled build returns `Error::Cancelled` from
+// `try_extend_parallel`, or just returns from `extend` and `extend_parallel`, keeping the points
+// inserted so far.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Progress callback and cancellation token of a builder, which are not part of the index and are
+// left out when the builder is serialized
+#[derive(Clone, Default)]
+pub(crate) struct BuildMonitor {
+    pub(crate) progress: Option<ProgressFn>,
+    pub(crate) cancel: Option<CancellationToken>,
+}
+
+impl BuildMonitor {
+    pub(crate) fn new(options: &NSWOptions) -> Self {
+        Self {
+            progress: options.progress.clone(),
+            cancel: options.cancel.clone(),
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        match &self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn report(&self, inserted: usize, layers: usize, started: Instant) {
+        if let Some(progress) = &self.progress {
+            progress(Progress {
+                inserted,
+                layers,
+                elapsed: started.elapsed(),
+            });
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{
+    nsw, verify::mark_reachable, Error, Graph, Idx, IdxExt, Result, SearchGraph, SimpleGraph,
+};
+
+// Connect every node of a layer that is not reachable from `ep` to the nearest reachable node found
+// by searching for it. A single edge makes all nodes connected to the node reachable, and nodes with
+// fewer than `max_connections` connections are preferred, such that few nodes get more connections
+// than allowed. Returns the number of edges added.
+pub(crate) fn repair_layer<T>(
+    graph: &mut SimpleGraph<T>,
+    ep: Idx,
+    ef: usize,
+    max_connections: usize,
+    distance_fn: impl Fn(&T, &T) -> usize,
+) -> Result<usize> {
+    let mut reached = vec![false; graph.size()];
+    mark_reachable(graph, ep, &mut reached);
+
+    let mut added = 0;
+    for v in 0..reached.len() {
+        if reached[v] {
+            continue;
+        }
+        let node = Idx::from_usize(v);
+
+        // Searches start from the entry point, so they only find reachable nodes
+        let point = graph.node(node).ok_or(Error::NodeNotFound(node))?;
+        let candidates = nsw::search(&*graph, point, ef, ep, &distance_fn)?
+            .drain_asc()
+            .map(|dist| dist.key)
+            .collect::<Vec<_>>();
+        // The nearest node with room, or else the one with the fewest connections
+        let target = candidates
+            .iter()
+            .copied()
+            .find(|&w| graph.degree(w) < max_connections)
+            .or_else(|| candidates.iter().copied().min_by_key(|&w| graph.degree(w)))
+            .ok_or(Error::NodeNotFound(ep))?;
+
+        graph.add_edge(node, target);
+        mark_reachable(graph, node, &mut reached);
+        added += 1;
+    }
+
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair_layer() {
+        let mut graph = SimpleGraph::from_iter(0..10);
+        graph.add_edges([(0, 1), (1, 2), (2, 3), (5, 6)].into_iter());
+
+        let added = repair_layer(&mut graph, 0, 4, 2, |a: &i32, b: &i32| {
+            a.abs_diff(*b) as usize
+        });
+        assert_eq!(added.unwrap(), 5);
+
+        let mut reached = vec![false; 10];
+        mark_reachable(&graph, 0, &mut reached);
+        assert!(reached.iter().all(|&r| r));
+        assert!((0..10).all(|v| graph.degree(v) <= 2));
+
+        // Every component is connected once, to the nearest node with room for another connection
+        for (v, w) in [(4, 3), (5, 4), (7, 6), (8, 7), (9, 8)] {
+            assert!(graph.is_connected(v, w));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{or_empty, Distance, Error, IdxExt, Index, Point, Result};
+
+// Computes the proxy of a point that the index of a `Rerank` holds for it, e.g. the sketch of a
+// vector. Implemented for functions from points to proxies.
+pub trait Proxy<P> {
+    type Output;
+
+    fn proxy(&self, point: &P) -> Self::Output;
+}
+
+impl<P, Q, F: Fn(&P) -> Q> Proxy<P> for F {
+    type Output = Q;
+
+    fn proxy(&self, point: &P) -> Q {
+        self(point)
+    }
+}
+
+// Two-stage index: candidates are found by an index over cheap proxies of the points, e.g. binary
+// sketches, and re-ranked by the exact distance between the original points. Key `i` of the index
+// must be the proxy of `points[i]`. With a `Proxy` it is an `Index` of the points, searched with
+// the proxy of every query.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rerank<I, P, X = ()> {
+    index: I,
+    points: Vec<P>,
+    // Number of candidates fetched for every neighbor to find
+    factor: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    proxy: X,
+}
+
+impl<I, P> Rerank<I, P> {
+    pub fn new(index: I, points: Vec<P>, factor: usize) -> Result<Self> {
+        if factor == 0 {
+            return Err(Error::InvalidParameter {
+                name: "factor",
+                reason: "must be positive",
+            });
+        }
+
+        Ok(Self {
+            index,
+            points,
+            factor,
+            proxy: (),
+        })
+    }
+}
+
+impl<I, P, X> Rerank<I, P, X> {
+    pub fn with_proxy<Y>(self, proxy: Y) -> Rerank<I, P, Y> {
+        Rerank {
+            index: self.index,
+            points: self.points,
+            factor: self.factor,
+            proxy,
+        }
+    }
+
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    pub fn points(&self) -> &[P] {
+        &self.points
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    pub fn size(&self) -> usize {
+        self.points.len()
+    }
+
+    // Search the index for `k * factor` candidates nearest to `query` and return the `k` of them
+    // nearest to `exact`, the original point of `query`
+    pub fn search<'a, Q>(
+        &'a self,
+        query: &Q,
+        exact: &P,
+        k: usize,
+        ef: usize,
+    ) -> Vec<Distance<'a, P>>
+    where
+        I: Index<Q>,
+        Q: Point,
+        P: Point,
+    {
+        or_empty(self.try_search(query, exact, k, ef))
+    }
+
+    pub fn try_search<'a, Q>(
+        &'a self,
+        query: &Q,
+        exact: &P,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        I: Index<Q>,
+        Q: Point,
+        P: Point,
+    {
+        let candidates = self.index.try_search(query, k * self.factor, ef)?;
+        if let Some(d) = candidates
+            .iter()
+            .find(|d| d.key.into_usize() >= self.points.len())
+        {
+            return Err(Error::OutOfRange {
+                index: d.key.into_usize(),
+                len: self.points.len(),
+            });
+        }
+
+        Ok(self.rerank(candidates, exact, k))
+    }
+
+    fn rerank<Q>(
+        &self,
+        candidates: Vec<Distance<'_, Q>>,
+        exact: &P,
+        k: usize,
+    ) -> Vec<Distance<'_, P>>
+    where
+        P: Point,
+    {
+        let mut res = candidates
+            .into_iter()
+            .map(|d| {
+                let point = &self.points[d.key.into_usize()];
+                Distance::new(exact.distance(point), d.key, point)
+            })
+            .collect::<Vec<_>>();
+
+        res.sort();
+        res.dedup();
+        res.truncate(k);
+        res
+    }
+
+    pub fn knns<Q>(
+        &self,
+        queries: impl IntoIterator<Item = (Q, P)>,
+        k: usize,
+        ef: usize,
+    ) -> Vec<Vec<Distance<'_, P>>>
+    where
+        Self: Sync,
+        I: Index<Q>,
+        Q: Point + Send,
+        P: Point + Send + Sync,
+    {
+        queries
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(query, exact)| self.search(&query, &exact, k, ef))
+            .collect()
+    }
+}
+
+impl<I, P, X> Index<P> for Rerank<I, P, X>
+where
+    I: Index<X::Output>,
+    X: Proxy<P>,
+    X::Output: Point,
+{
+    fn size(&self) -> usize {
+        self.points.len()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        or_empty(Index::try_search(self, query, k, ef))
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        Rerank::try_search(self, &self.proxy.proxy(query), query, k, ef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bruteforce;
+
+    #[test]
+    fn test_rerank() {
+        let points = (0..100).collect::<Vec<i32>>();
+        // Proxies only tell points apart to the nearest ten
+        let proxies = points.iter().map(|p| p / 10).collect::<Bruteforce<_>>();
+        let rerank = Rerank::new(proxies, points, 3).unwrap();
+
+        assert_eq!(rerank.size(), 100);
+
+        let res = rerank.search(&5, &58, 5, 15);
+        assert_eq!(
+            res.iter().map(|d| d.key).collect::<Vec<_>>(),
+            vec![58, 57, 59, 56, 55]
+        );
+        assert!(res.iter().all(|d| *d.point as usize == d.key.into_usize()));
+        assert_eq!(
+            res.iter().map(|d| d.distance).collect::<Vec<_>>(),
+            vec![0, 1, 1, 2, 3]
+        );
+
+        // Without enough candidates the nearest neighbors are missed
+        let rerank = Rerank::new(rerank.index, rerank.points, 1).unwrap();
+        assert_eq!(rerank.search(&5, &58, 5, 5)[0].key, 54);
+
+        assert!(matches!(
+            Rerank::new(rerank.index, rerank.points, 0),
+            Err(Error::InvalidParameter { name: "factor", .. })
+        ));
+    }
+
+    #[test]
+    fn test_rerank_index() {
+        let points = (0..100).collect::<Vec<i32>>();
+        let proxies = points.iter().map(|p| p / 10).collect::<Bruteforce<_>>();
+        let rerank = Rerank::new(proxies, points, 3)
+            .unwrap()
+            .with_proxy(|p: &i32| p / 10);
+
+        assert_eq!(Index::size(&rerank), 100);
+        let res = Index::search(&rerank, &58, 5, 15);
+        assert_eq!(
+            res.iter().map(|d| d.key).collect::<Vec<_>>(),
+            vec![58, 57, 59, 56, 55]
+        );
+
+        // Keys of the index past the points are reported, not looked up
+        let rerank = Rerank::new((0..20).collect::<Bruteforce<_>>(), vec![0; 10], 1)
+            .unwrap()
+            .with_proxy(|p: &i32| *p);
+        assert!(matches!(
+            Index::try_search(&rerank, &15, 1, 1),
+            Err(Error::OutOfRange { index: 15, len: 10 })
+        ));
+        assert!(Index::search(&rerank, &15, 1, 1).is_empty());
+    }
+}
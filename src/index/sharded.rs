@@ -0,0 +1,157 @@
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Distance, Idx, IdxExt, Index, Point, Result};
+
+// Index partitioned into shards that are searched in parallel. Each shard holds a contiguous
+// range of the dataset starting at its offset, so keys are translated back to global ids.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ShardedIndex<I> {
+    shards: Vec<(usize, I)>,
+}
+
+impl<I> Default for ShardedIndex<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> ShardedIndex<I> {
+    pub const fn new() -> Self {
+        Self { shards: vec![] }
+    }
+
+    pub fn add_shard(&mut self, offset: usize, shard: I) {
+        self.shards.push((offset, shard));
+    }
+
+    pub fn shards(&self) -> &Vec<(usize, I)> {
+        &self.shards
+    }
+
+    // Shards to change in place, e.g. to repair, which must keep the keys of their points
+    pub fn shards_mut(&mut self) -> impl Iterator<Item = &mut I> {
+        self.shards.iter_mut().map(|(_, shard)| shard)
+    }
+}
+
+impl<I> FromIterator<(usize, I)> for ShardedIndex<I> {
+    fn from_iter<T: IntoIterator<Item = (usize, I)>>(iter: T) -> Self {
+        Self {
+            shards: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<P: Sync, I: Index<P> + Sync> Index<P> for ShardedIndex<I> {
+    fn size(&self) -> usize {
+        self.shards.iter().map(|(_, shard)| shard.size()).sum()
+    }
+
+    fn search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Vec<Distance<'a, P>>
+    where
+        P: Point,
+    {
+        let mut res = self
+            .shards
+            .par_iter()
+            .flat_map_iter(|(offset, shard)| {
+                shard
+                    .search(query, k, ef)
+                    .into_iter()
+                    .map(move |d| Distance::new(d.distance, global(d.key, *offset), d.point))
+            })
+            .collect::<Vec<_>>();
+
+        res.sort();
+        res.dedup();
+        res.truncate(k);
+        res
+    }
+
+    fn try_search<'a>(&'a self, query: &P, k: usize, ef: usize) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let mut res = self
+            .shards
+            .par_iter()
+            .map(|(offset, shard)| {
+                Ok(shard
+                    .try_search(query, k, ef)?
+                    .into_iter()
+                    .map(move |d| Distance::new(d.distance, global(d.key, *offset), d.point)))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        res.sort();
+        res.dedup();
+        res.truncate(k);
+        Ok(res)
+    }
+
+    fn try_search_skipping<'a>(
+        &'a self,
+        query: &P,
+        k: usize,
+        ef: usize,
+        skip: &(dyn Fn(Idx) -> bool + Sync),
+    ) -> Result<Vec<Distance<'a, P>>>
+    where
+        P: Point,
+    {
+        let mut res = self
+            .shards
+            .par_iter()
+            .map(|(offset, shard)| {
+                Ok(shard
+                    .try_search_skipping(query, k, ef, &|key| skip(global(key, *offset)))?
+                    .into_iter()
+                    .map(move |d| Distance::new(d.distance, global(d.key, *offset), d.point)))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        res.sort();
+        res.dedup();
+        res.truncate(k);
+        Ok(res)
+    }
+}
+
+fn global(key: Idx, offset: usize) -> Idx {
+    Idx::from_usize(key.into_usize() + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bruteforce;
+
+    #[test]
+    fn test_sharded() {
+        let points = (0..100).collect::<Vec<_>>();
+        let sharded = points
+            .chunks(30)
+            .enumerate()
+            .map(|(i, chunk)| (i * 30, chunk.iter().copied().collect::<Bruteforce<_>>()))
+            .collect::<ShardedIndex<_>>();
+
+        assert_eq!(sharded.shards().len(), 4);
+        assert_eq!(sharded.size(), 100);
+
+        let res = sharded.search(&58, 5, 5);
+        assert_eq!(
+            res.iter().map(|d| d.key).collect::<Vec<_>>(),
+            vec![58, 57, 59, 56, 60]
+        );
+        assert!(res.iter().all(|d| *d.point as usize == d.key.into_usize()));
+    }
+}
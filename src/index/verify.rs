@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::{self, Display},
+};
+
+use crate::{Graph, Idx, IdxExt, SimpleGraph};
+
+// Broken invariant of a graph index found by `verify`. Layers are numbered from the base layer up,
+// such that the base layer is layer 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    // Connection to a node that is not in the layer
+    NeighborOutOfRange {
+        layer: usize,
+        node: Idx,
+        neighbor: Idx,
+    },
+    SelfLoop {
+        layer: usize,
+        node: Idx,
+    },
+    // Connection that the neighbor does not have back, as edges are undirected
+    OneWay {
+        layer: usize,
+        node: Idx,
+        neighbor: Idx,
+    },
+    // More connections than inserting points leaves, which is `max_connections + 1` as a node keeps
+    // the connection to the point being inserted when its connections are pruned
+    Degree {
+        layer: usize,
+        node: Idx,
+        degree: usize,
+        max: usize,
+    },
+    // Node of an upper layer linking to a node that is not in the layer below, or that another node
+    // links to as well
+    BrokenLink {
+        layer: usize,
+        node: Idx,
+        link: Idx,
+    },
+    // Entry point that is missing or not in the layer, such that reachability is not checked
+    EntryPoint {
+        layer: usize,
+        ep: Option<Idx>,
+    },
+    // Node that no search of the layer can find, as it is not reachable from the entry point
+    Unreachable {
+        layer: usize,
+        node: Idx,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NeighborOutOfRange {
+                layer,
+                node,
+                neighbor,
+            } => write!(
+                f,
+                "layer {layer}: node {node} is connected to {neighbor}, which is not in the layer"
+            ),
+            Self::SelfLoop { layer, node } => {
+                write!(f, "layer {layer}: node {node} is connected to itself")
+            }
+            Self::OneWay {
+                layer,
+                node,
+                neighbor,
+            } => write!(
+                f,
+                "layer {layer}: node {node} is connected to {neighbor}, which is not connected back"
+            ),
+            Self::Degree {
+                layer,
+                node,
+                degree,
+                max,
+            } => write!(
+                f,
+                "layer {layer}: node {node} has {degree} connections, more than {max}"
+            ),
+            Self::BrokenLink { layer, node, link } => write!(
+                f,
+                "layer {layer}: node {node} links to {link}, which is not its own node in the layer below"
+            ),
+            Self::EntryPoint { layer, ep: Some(ep) } => {
+                write!(f, "layer {layer}: entry point {ep} is not in the layer")
+            }
+            Self::EntryPoint { layer, ep: None } => {
+                write!(f, "layer {layer}: has nodes but no entry point")
+            }
+            Self::Unreachable { layer, node } => write!(
+                f,
+                "layer {layer}: node {node} is not reachable from the entry point"
+            ),
+        }
+    }
+}
+
+// Check the connections of every node of a layer, and that all nodes are reachable from `ep`
+pub(crate) fn verify_layer<T>(
+    graph: &SimpleGraph<T>,
+    layer: usize,
+    ep: Option<Idx>,
+    max_connections: Option<usize>,
+    violations: &mut Vec<Violation>,
+) {
+    let size = graph.size();
+
+    for (v, adj_list) in graph.adj_lists().iter().enumerate() {
+        let node = Idx::from_usize(v);
+
+        for &neighbor in adj_list {
+            if neighbor == node {
+                violations.push(Violation::SelfLoop { layer, node });
+            } else if neighbor.into_usize() >= size {
+                violations.push(Violation::NeighborOutOfRange {
+                    layer,
+                    node,
+                    neighbor,
+                });
+            } else if !graph.adj_lists()[neighbor.into_usize()].contains(&node) {
+                violations.push(Violation::OneWay {
+                    layer,
+                    node,
+                    neighbor,
+                });
+            }
+        }
+
+        if let Some(max) = max_connections.map(|m| m + 1) {
+            if adj_list.len() > max {
+                violations.push(Violation::Degree {
+                    layer,
+                    node,
+                    degree: adj_list.len(),
+                    max,
+                });
+            }
+        }
+    }
+
+    let Some(ep) = ep.filter(|ep| ep.into_usize() < size) else {
+        if size > 0 {
+            violations.push(Violation::EntryPoint { layer, ep });
+        }
+        return;
+    };
+
+    let mut reached = vec![false; size];
+    mark_reachable(graph, ep, &mut reached);
+
+    violations.extend(
+        reached
+            .iter()
+            .enumerate()
+            .filter(|(_, &r)| !r)
+            .map(|(v, _)| Violation::Unreachable {
+                layer,
+                node: Idx::from_usize(v),
+            }),
+    );
+}
+
+// Mark the nodes reachable from `v` that are not marked yet, with a breadth-first search that does
+// not go past marked nodes
+pub(crate) fn mark_reachable<T>(graph: &SimpleGraph<T>, v: Idx, reached: &mut [bool]) {
+    let Some(r) = reached.get_mut(v.into_usize()) else {
+        return;
+    };
+    *r = true;
+
+    let mut queue = VecDeque::from([v]);
+    while let Some(v) = queue.pop_front() {
+        for &w in graph.neighborhood(v) {
+            if let Some(r) = reached.get_mut(w.into_usize()).filter(|r| !**r) {
+                *r = true;
+                queue.push_back(w);
+            }
+        }
+    }
+}
+
+// Check that every node of an upper layer links to its own node in the layer below
+pub(crate) fn verify_links<T>(
+    graph: &SimpleGraph<(T, Idx)>,
+    layer: usize,
+    below: usize,
+    violations: &mut Vec<Violation>,
+) {
+    let mut linked = HashSet::with_capacity(graph.size());
+
+    for (v, (_, link)) in graph.nodes().iter().enumerate() {
+        if link.into_usize() >= below || !linked.insert(*link) {
+            violations.push(Violation::BrokenLink {
+                layer,
+                node: Idx::from_usize(v),
+                link: *link,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HNSWBuilder, IndexBuilder, NSWOptions};
+
+    #[test]
+    fn test_verify() {
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 3,
+            max_connections: 4,
+            size: 500,
+            ..NSWOptions::default()
+        });
+        builder.extend(0..500);
+        assert_eq!(builder.build().verify(Some(4)), vec![]);
+
+        let mut graph = SimpleGraph::from_iter(0..6);
+        graph.add_edges([(0, 1), (0, 2), (0, 3), (0, 4), (3, 3)].into_iter());
+        let mut violations = vec![];
+        verify_layer(&graph, 0, Some(0), Some(2), &mut violations);
+        assert_eq!(
+            violations,
+            vec![
+                Violation::Degree {
+                    layer: 0,
+                    node: 0,
+                    degree: 4,
+                    max: 3
+                },
+                Violation::SelfLoop { layer: 0, node: 3 },
+                Violation::Unreachable { layer: 0, node: 5 },
+            ]
+        );
+
+        let graph =
+            SimpleGraph::from_adj_lists(vec![(); 2], vec![HashSet::from([1]), HashSet::new()]);
+        let mut violations = vec![];
+        verify_layer(&graph, 0, Some(0), None, &mut violations);
+        assert_eq!(
+            violations,
+            vec![Violation::OneWay {
+                layer: 0,
+                node: 0,
+                neighbor: 1
+            }]
+        );
+
+        let layer = SimpleGraph::from_iter([(0, 2), (1, 2), (2, 6)]);
+        let mut violations = vec![];
+        verify_links(&layer, 1, 6, &mut violations);
+        verify_layer(&layer, 1, Some(3), None, &mut violations);
+        assert_eq!(
+            violations,
+            vec![
+                Violation::BrokenLink {
+                    layer: 1,
+                    node: 1,
+                    link: 2
+                },
+                Violation::BrokenLink {
+                    layer: 1,
+                    node: 2,
+                    link: 6
+                },
+                Violation::EntryPoint {
+                    layer: 1,
+                    ep: Some(3)
+                },
+            ]
+        );
+    }
+}
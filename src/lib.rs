@@ -1,8 +1,12 @@
 mod collections;
+mod error;
 mod index;
+mod metrics;
 
 pub use crate::collections::*;
+pub use crate::error::*;
 pub use crate::index::*;
+pub use crate::metrics::*;
 
 #[cfg(test)]
 mod test_utils {
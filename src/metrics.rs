@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{Bruteforce, Distance, IdxExt, Index, Point};
+
+// True nearest neighbors of a query, sorted by distance
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Neighbors {
+    pub ids: Vec<usize>,
+    pub distances: Vec<usize>,
+}
+
+impl<'a, P> FromIterator<Distance<'a, P>> for Neighbors {
+    fn from_iter<T: IntoIterator<Item = Distance<'a, P>>>(iter: T) -> Self {
+        let (ids, distances) = iter
+            .into_iter()
+            .map(|d| (d.key.into_usize(), d.distance))
+            .unzip();
+        Self { ids, distances }
+    }
+}
+
+// Fraction of the `k` nearest neighbors found among the first `k` ids of `result`. `truth` must be
+// sorted by `truth_distances` in ascending order and can be longer than `k`. Every id in `truth`
+// tied with the k-th nearest neighbor counts as a true neighbor, so results are not penalized for
+// picking a different neighbor at the same distance.
+pub fn recall<D: PartialOrd>(
+    result: &[usize],
+    truth: &[usize],
+    truth_distances: &[D],
+    k: usize,
+) -> f64 {
+    let k = k.min(truth.len()).min(truth_distances.len());
+    if k == 0 {
+        return 1.0;
+    }
+
+    let threshold = &truth_distances[k - 1];
+    let neighbors = truth
+        .iter()
+        .zip(truth_distances)
+        .take_while(|(_, d)| *d <= threshold)
+        .map(|(id, _)| id)
+        .collect::<HashSet<_>>();
+
+    let found = result
+        .iter()
+        .take(k)
+        .collect::<HashSet<_>>()
+        .intersection(&neighbors)
+        .count();
+
+    found as f64 / k as f64
+}
+
+// Sum of the distances of the first `k` results relative to the sum of the `k` smallest distances.
+// `None` if the true distances sum to 0.
+pub fn distance_ratio(result_distances: &[f64], truth_distances: &[f64], k: usize) -> Option<f64> {
+    let mut result_distances = result_distances.to_vec();
+    result_distances.sort_by(f64::total_cmp);
+
+    let result = result_distances.iter().take(k).sum::<f64>();
+    let truth = truth_distances.iter().take(k).sum::<f64>();
+
+    (truth > 0.0).then(|| result / truth)
+}
+
+// The `k` nearest neighbors of every query found by exhaustive search
+pub fn exact_neighbors<P: Point + Sync>(
+    bruteforce: &Bruteforce<P>,
+    queries: &[P],
+    k: usize,
+) -> Vec<Neighbors> {
+    queries
+        .par_iter()
+        .map(|q| bruteforce.search(q, k, 0).into_iter().collect())
+        .collect()
+}
+
+// Recall@k averaged over `queries` when searching `index` with `ef`
+pub fn mean_recall<P, I>(index: &I, queries: &[P], truth: &[Neighbors], k: usize, ef: usize) -> f64
+where
+    P: Point + Sync,
+    I: Index<P> + Sync,
+{
+    let total = queries
+        .par_iter()
+        .zip(truth)
+        .map(|(q, truth)| {
+            let ids = index
+                .search(q, k, ef)
+                .into_iter()
+                .map(|d| d.key.into_usize())
+                .collect::<Vec<_>>();
+            recall(&ids, &truth.ids, &truth.distances, k)
+        })
+        .sum::<f64>();
+
+    total / queries.len().max(1) as f64
+}
+
+// Smallest `ef` up to `max_ef` whose mean recall@k over `queries` reaches `target`, along with
+// that recall. Recall is assumed to grow with `ef`, so it is found by binary search. `None` if
+// even `max_ef` falls short of the target.
+pub fn tune_ef<P, I>(
+    index: &I,
+    queries: &[P],
+    truth: &[Neighbors],
+    k: usize,
+    target: f64,
+    max_ef: usize,
+) -> Option<(usize, f64)>
+where
+    P: Point + Sync,
+    I: Index<P> + Sync,
+{
+    let mut best = mean_recall(index, queries, truth, k, max_ef);
+    if best < target {
+        return None;
+    }
+
+    let (mut lo, mut hi) = (1, max_ef);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let recall = mean_recall(index, queries, truth, k, mid);
+
+        if recall >= target {
+            hi = mid;
+            best = recall;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Some((hi, best))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HNSWBuilder, IndexBuilder, NSWOptions};
+
+    #[test]
+    fn test_recall() {
+        let truth = [1, 2, 3, 4, 5];
+        let distances = [0, 1, 2, 2, 3];
+
+        assert_eq!(recall(&[1, 2, 3], &truth, &distances, 3), 1.0);
+        // 4 is tied with 3 as the 3rd nearest neighbor
+        assert_eq!(recall(&[4, 2, 1], &truth, &distances, 3), 1.0);
+        assert_eq!(recall(&[5, 2, 1], &truth, &distances, 3), 2.0 / 3.0);
+        assert_eq!(recall(&[2, 2, 2], &truth, &distances, 3), 1.0 / 3.0);
+        assert_eq!(recall(&[1, 2, 5, 3], &truth, &distances, 3), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn test_distance_ratio() {
+        assert_eq!(
+            distance_ratio(&[3.0, 1.0, 2.0], &[1.0, 2.0, 3.0], 3),
+            Some(1.0)
+        );
+        assert_eq!(
+            distance_ratio(&[4.0, 2.0, 2.0], &[1.0, 2.0, 3.0], 3),
+            Some(8.0 / 6.0)
+        );
+        assert_eq!(distance_ratio(&[1.0], &[0.0], 1), None);
+    }
+
+    #[test]
+    fn test_tune_ef() {
+        let k = 10;
+        let points = (0..1000).map(|i| i * 7 % 1000).collect::<Vec<_>>();
+        let queries = (0..50).map(|i| i * 20 + 3).collect::<Vec<_>>();
+
+        let mut builder = HNSWBuilder::new(NSWOptions {
+            ef_construction: 8,
+            connections: 4,
+            size: points.len(),
+            ..NSWOptions::default()
+        });
+        builder.extend(points.clone());
+        let hnsw = builder.build();
+
+        let bruteforce = points.into_iter().collect::<Bruteforce<_>>();
+        let truth = exact_neighbors(&bruteforce, &queries, k);
+        assert_eq!(mean_recall(&bruteforce, &queries, &truth, k, 0), 1.0);
+
+        let (ef, recall) = tune_ef(&hnsw, &queries, &truth, k, 0.99, 256).unwrap();
+        assert!(recall >= 0.99);
+        assert_eq!(mean_recall(&hnsw, &queries, &truth, k, ef), recall);
+        if ef > 1 {
+            assert!(mean_recall(&hnsw, &queries, &truth, k, ef - 1) < 0.99);
+        }
+
+        assert_eq!(tune_ef(&hnsw, &queries, &truth, k, 1.1, 256), None);
+    }
+}